@@ -0,0 +1,92 @@
+//! shell 别名: alias name='command args'
+//!
+//! 别名保存在镜像根目录的 /.profile 中 (每行一条 alias name='command args'),
+//! 打开镜像时读入, 修改后写回. 输入的命令在解析之前先展开别名.
+
+use std::collections::BTreeMap;
+
+use crate::fs::Inode;
+
+/// 保存别名的文件 (位于根目录)
+pub const PROFILE: &str = ".profile";
+
+#[derive(Default)]
+pub struct Aliases(BTreeMap<String, String>);
+
+impl Aliases {
+    /// 从根目录的 /.profile 读入别名, 文件不存在 (或者是目录) 时返回空表
+    pub fn load(root_inode: &Inode) -> Self {
+        let mut aliases = Self::default();
+        if let Some(profile) = root_inode.find(PROFILE).filter(|profile| !profile.is_dir()) {
+            let mut buf = vec![0u8; profile.size()];
+            profile.read(0, &mut buf);
+            for line in String::from_utf8_lossy(&buf).lines() {
+                if let Some((name, command)) = line.trim().strip_prefix("alias ").and_then(parse) {
+                    aliases.0.insert(name, command);
+                }
+            }
+        }
+        aliases
+    }
+
+    /// 将别名写回根目录的 /.profile, /.profile 是目录时返回错误
+    pub fn save(&self, root_inode: &Inode) -> Result<(), String> {
+        let content: String = self
+            .0
+            .iter()
+            .map(|(name, command)| format!("alias {}='{}'\n", name, command))
+            .collect();
+        root_inode.replace_file(PROFILE, content.as_bytes())
+    }
+
+    /// 定义别名, definition 形如 name='command args'
+    pub fn define(&mut self, definition: &str) -> bool {
+        match parse(definition) {
+            Some((name, command)) => {
+                self.0.insert(name, command);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.0.remove(name).is_some()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.0.iter()
+    }
+
+    /// 展开命令行的第一个单词, 只展开一层, 避免别名之间相互引用导致死循环
+    pub fn expand(&self, line: &str) -> String {
+        let line = line.trim();
+        let (cmd, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        match self.0.get(cmd) {
+            Some(command) if rest.is_empty() => command.clone(),
+            Some(command) => format!("{} {}", command, rest),
+            None => line.to_string(),
+        }
+    }
+}
+
+/// 解析 name='command args' (也接受双引号或不带引号)
+fn parse(definition: &str) -> Option<(String, String)> {
+    let (name, command) = definition.split_once('=')?;
+    let name = name.trim();
+    let command = command.trim();
+    let command = command
+        .strip_prefix('\'')
+        .and_then(|command| command.strip_suffix('\''))
+        .or_else(|| {
+            command
+                .strip_prefix('"')
+                .and_then(|command| command.strip_suffix('"'))
+        })
+        .unwrap_or(command)
+        .trim();
+    if name.is_empty() || name.contains(char::is_whitespace) || command.is_empty() {
+        return None;
+    }
+    Some((name.to_string(), command.to_string()))
+}
@@ -0,0 +1,114 @@
+//! 可替换的数据块分配策略
+//!
+//! [`FileSystem`](super::FileSystem) 通过 [`Allocator`] 在数据块位图中挑选空闲的 bit,
+//! 挂载后可以通过 set_allocator 更换策略, 试验不同的分配策略时不需要修改 fs.rs.
+//!
+//! - [`FirstFit`]: 从头扫描位图, 分配第一个空闲的 bit (即 Bitmap::alloc, 默认策略)
+//! - [`NextFit`]: 从上一次分配的位置之后继续扫描, 到可分配范围的末尾后回到开头
+//! - [`BestFit`]: 从最短的连续空闲区段中分配, 尽量保留长的连续空闲区段.
+//!   区段用完或者有块被回收之前, 它一直是最短的区段, 因此接着在其中分配而不重新扫描位图
+//! - [`GroupAware`]: 以一个位图块管理的 4096 个块为一组, 优先在上一次分配所在的组中分配,
+//!   该组已满时换到空闲块最多的组, 使同一个文件的块尽量聚集
+
+use std::sync::Arc;
+
+use super::{Bitmap, BlockDevice, BLOCK_BITS};
+
+/// 分配策略: 在位图中选择并分配一个空闲的 bit, 返回其编号, 位图已满时返回 None
+///
+/// 只能分配编号小于 Bitmap::usable 的 bit
+pub trait Allocator: Send {
+    fn alloc(&mut self, bitmap: &Bitmap, block_device: &Arc<dyn BlockDevice>) -> Option<usize>;
+
+    /// bit 被回收之后调用, 缓存了位图状态的策略需要在此时丢弃缓存
+    fn dealloc(&mut self, _bit: usize) {}
+}
+
+/// 通过名称获取分配策略: first-fit, next-fit, best-fit, group-aware
+pub fn allocator_by_name(name: &str) -> Option<Box<dyn Allocator>> {
+    match name {
+        "first-fit" => Some(Box::new(FirstFit)),
+        "next-fit" => Some(Box::new(NextFit::default())),
+        "best-fit" => Some(Box::new(BestFit::default())),
+        "group-aware" => Some(Box::new(GroupAware::default())),
+        _ => None,
+    }
+}
+
+#[derive(Default)]
+pub struct FirstFit;
+
+impl Allocator for FirstFit {
+    fn alloc(&mut self, bitmap: &Bitmap, block_device: &Arc<dyn BlockDevice>) -> Option<usize> {
+        bitmap.alloc(block_device)
+    }
+}
+
+#[derive(Default)]
+pub struct NextFit {
+    /// 下一次开始扫描的位置
+    next: usize,
+}
+
+impl Allocator for NextFit {
+    fn alloc(&mut self, bitmap: &Bitmap, block_device: &Arc<dyn BlockDevice>) -> Option<usize> {
+        let bit = bitmap
+            .first_free_in(block_device, self.next, bitmap.usable())
+            .or_else(|| bitmap.first_free_in(block_device, 0, self.next))?;
+        bitmap.alloc_bit(block_device, bit);
+        self.next = bit + 1;
+        Some(bit)
+    }
+}
+
+#[derive(Default)]
+pub struct BestFit {
+    /// 正在使用的区段中剩余的部分 [next, end)
+    extent: Option<(usize, usize)>,
+}
+
+impl Allocator for BestFit {
+    fn alloc(&mut self, bitmap: &Bitmap, block_device: &Arc<dyn BlockDevice>) -> Option<usize> {
+        let (bit, end) = match self.extent {
+            Some((next, end)) if next < end && !bitmap.is_allocated(block_device, next) => {
+                (next, end)
+            }
+            _ => {
+                let (start, len) = bitmap.shortest_free_extent(block_device)?;
+                (start, start + len)
+            }
+        };
+        bitmap.alloc_bit(block_device, bit);
+        self.extent = Some((bit + 1, end));
+        Some(bit)
+    }
+
+    fn dealloc(&mut self, _bit: usize) {
+        // 回收的块可能组成更短的区段
+        self.extent = None;
+    }
+}
+
+#[derive(Default)]
+pub struct GroupAware {
+    /// 上一次分配所在的组 (位图区域中的块编号)
+    group: usize,
+}
+
+impl Allocator for GroupAware {
+    fn alloc(&mut self, bitmap: &Bitmap, block_device: &Arc<dyn BlockDevice>) -> Option<usize> {
+        if bitmap.free_in_block(block_device, self.group) == 0 {
+            let (group, free) = (0..bitmap.blocks())
+                .map(|group| (group, bitmap.free_in_block(block_device, group)))
+                .max_by_key(|&(_, free)| free)?;
+            if free == 0 {
+                return None;
+            }
+            self.group = group;
+        }
+        let start = self.group * BLOCK_BITS;
+        let bit = bitmap.first_free_in(block_device, start, start + BLOCK_BITS)?;
+        bitmap.alloc_bit(block_device, bit);
+        Some(bit)
+    }
+}
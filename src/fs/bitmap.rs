@@ -25,13 +25,24 @@ pub struct Bitmap {
     start_block_id: usize,
     /// 位图索引使用的磁盘块数
     blocks_counts: usize,
+    /// 实际可以分配的 bit 数量, 编号不小于它的 bit 没有对应的索引节点/数据块
+    usable_bits: usize,
 }
 
 impl Bitmap {
     pub fn new(start_block_id: usize, blocks_counts: usize) -> Self {
+        Self::with_usable(start_block_id, blocks_counts, blocks_counts * BLOCK_BITS)
+    }
+
+    /// 只有前 usable_bits 个 bit 可以分配的位图
+    ///
+    /// 数据块位图的最后一个块通常不会被用满 (数据区域的块数不是 4096 的整数倍),
+    /// 超出数据区域的 bit 一旦被分配出去, 得到的块号就会越过块设备的末尾
+    pub fn with_usable(start_block_id: usize, blocks_counts: usize, usable_bits: usize) -> Self {
         Self {
             start_block_id,
             blocks_counts,
+            usable_bits: usable_bits.min(blocks_counts * BLOCK_BITS),
         }
     }
 
@@ -85,6 +96,10 @@ impl Bitmap {
                     .find(|(_, bits64)| **bits64 != u64::MAX)
                     // 则通过 u64::trailing_ones 找到最低的一个 0 的位置(从第 0 位开始计算)
                     .map(|(bits64_pos, bits64)| (bits64_pos, bits64.trailing_ones() as usize))
+                    // 最低的空闲 bit 已经超出可分配的范围时, 后面的 bit 同样不可分配
+                    .filter(|(bits64_pos, inner_pos)| {
+                        block_id * BLOCK_BITS + bits64_pos * 64 + inner_pos < self.usable_bits
+                    })
                 // 在此处返回 if let 匹配的bits64_pos, inner_pos = bits64.trailing_ones()
                 {
                     // 或运算 将该位置置为 1
@@ -133,10 +148,183 @@ impl Bitmap {
         });
     }
 
+    // 以下方法供 Allocator 实现不同的分配策略使用, alloc 本身就是 first-fit 策略
+
+    /// 拷贝位图区域中第 block_id 个块的内容
+    fn read_bitmap_block(
+        &self,
+        block_device: &Arc<dyn BlockDevice>,
+        block_id: usize,
+    ) -> BitmapBlock {
+        get_block_cache(block_id + self.start_block_id, Arc::clone(block_device))
+            .lock()
+            .read(0, |bitmap_block: &BitmapBlock| *bitmap_block)
+    }
+
+    /// 在 bit 编号区间 [start, end) 中找到第一个空闲的 bit (不分配)
+    pub fn first_free_in(
+        &self,
+        block_device: &Arc<dyn BlockDevice>,
+        start: usize,
+        end: usize,
+    ) -> Option<usize> {
+        let end = end.min(self.usable_bits);
+        if start >= end {
+            return None;
+        }
+        for block_id in start / BLOCK_BITS..end.div_ceil(BLOCK_BITS) {
+            let bitmap_block = self.read_bitmap_block(block_device, block_id);
+            for (bits64_pos, bits64) in bitmap_block.iter().enumerate() {
+                let base = block_id * BLOCK_BITS + bits64_pos * 64;
+                if base + 64 <= start || base >= end {
+                    continue;
+                }
+                // 将区间之外的 bit 视为已分配
+                let mut bits64 = *bits64;
+                if start > base {
+                    bits64 |= (1u64 << (start - base)) - 1;
+                }
+                if end < base + 64 {
+                    bits64 |= !((1u64 << (end - base)) - 1);
+                }
+                if bits64 != u64::MAX {
+                    return Some(base + bits64.trailing_ones() as usize);
+                }
+            }
+        }
+        None
+    }
+
+    /// 最短的连续空闲区段 (起始 bit, 长度), 只包括可分配的 bit, 长度相同时返回最靠前的区段
+    ///
+    /// 只遍历一次位图, 每次跳过一段连续的 0 或 1, 全 0 或全 1 的 u64 一步跳过.
+    /// 找到长度为 1 的区段时不会再有更短的, 提前返回
+    pub fn shortest_free_extent(
+        &self,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Option<(usize, usize)> {
+        let mut shortest: Option<(usize, usize)> = None;
+        // 正在统计的区段
+        let mut current: Option<(usize, usize)> = None;
+        let mut close = |current: &mut Option<(usize, usize)>| {
+            if let Some((start, len)) = current.take() {
+                if shortest.is_none_or(|(_, shortest_len)| len < shortest_len) {
+                    shortest = Some((start, len));
+                }
+            }
+            shortest.is_some_and(|(_, len)| len == 1)
+        };
+        for block_id in 0..self.usable_bits.div_ceil(BLOCK_BITS) {
+            let found = get_block_cache(block_id + self.start_block_id, Arc::clone(block_device))
+                .lock()
+                .read(0, |bitmap_block: &BitmapBlock| {
+                    for (bits64_pos, bits64) in bitmap_block.iter().enumerate() {
+                        let base = block_id * BLOCK_BITS + bits64_pos * 64;
+                        if base >= self.usable_bits {
+                            break;
+                        }
+                        // 将不可分配的 bit 视为已分配
+                        let mut bits64 = *bits64;
+                        if self.usable_bits < base + 64 {
+                            bits64 |= !((1u64 << (self.usable_bits - base)) - 1);
+                        }
+                        let mut inner_pos = 0;
+                        while inner_pos < 64 {
+                            let rest = bits64 >> inner_pos;
+                            if rest & 1 == 0 {
+                                // 一段连续的空闲 bit, 移入的高位都是 0, 因此需要截断到组的末尾
+                                let len = (rest.trailing_zeros() as usize).min(64 - inner_pos);
+                                current = Some(match current {
+                                    Some((start, current_len)) => (start, current_len + len),
+                                    None => (base + inner_pos, len),
+                                });
+                                inner_pos += len;
+                            } else {
+                                if close(&mut current) {
+                                    return true;
+                                }
+                                inner_pos += rest.trailing_ones() as usize;
+                            }
+                        }
+                    }
+                    false
+                });
+            if found {
+                return shortest;
+            }
+        }
+        close(&mut current);
+        shortest
+    }
+
+    /// 位图区域中第 block_id 个块中空闲 (并且可分配) 的 bit 数量
+    pub fn free_in_block(&self, block_device: &Arc<dyn BlockDevice>, block_id: usize) -> usize {
+        let usable = self
+            .usable_bits
+            .saturating_sub(block_id * BLOCK_BITS)
+            .min(BLOCK_BITS);
+        if usable == 0 {
+            return 0;
+        }
+        let bitmap_block = self.read_bitmap_block(block_device, block_id);
+        usable.saturating_sub(
+            bitmap_block
+                .iter()
+                .map(|bits64| bits64.count_ones() as usize)
+                .sum::<usize>(),
+        )
+    }
+
+    /// 分配指定的 bit, 该 bit 必须是空闲的
+    pub fn alloc_bit(&self, block_device: &Arc<dyn BlockDevice>, bit: usize) {
+        let (block_id, bits64_pos, inner_pos) = decomposition(bit);
+        get_block_cache(block_id + self.start_block_id, Arc::clone(block_device))
+            .lock()
+            .modify(0, |bitmap_block: &mut BitmapBlock| {
+                assert!(bitmap_block[bits64_pos] & (1 << inner_pos) == 0);
+                bitmap_block[bits64_pos] |= 1u64 << inner_pos;
+            });
+    }
+
+    /// 指定的 bit 是否已经分配, 超出位图范围的 bit 视为未分配
+    pub fn is_allocated(&self, block_device: &Arc<dyn BlockDevice>, bit: usize) -> bool {
+        if bit >= self.maximum() {
+            return false;
+        }
+        let (block_id, bits64_pos, inner_pos) = decomposition(bit);
+        self.read_bitmap_block(block_device, block_id)[bits64_pos] & (1u64 << inner_pos) != 0
+    }
+
+    /// 位图区域占用的块数
+    pub fn blocks(&self) -> usize {
+        self.blocks_counts
+    }
+
+    /// 统计已经分配出去的 bit 数量
+    pub fn count_allocated(&self, block_device: &Arc<dyn BlockDevice>) -> usize {
+        (0..self.blocks_counts)
+            .map(|block_id| {
+                get_block_cache(block_id + self.start_block_id, Arc::clone(block_device))
+                    .lock()
+                    .read(0, |bitmap_block: &BitmapBlock| {
+                        bitmap_block
+                            .iter()
+                            .map(|bits64| bits64.count_ones() as usize)
+                            .sum::<usize>()
+                    })
+            })
+            .sum()
+    }
+
     /// 获取可分配块的最大数量
     pub fn maximum(&self) -> usize {
         self.blocks_counts * BLOCK_BITS
     }
+
+    /// 实际可以分配的 bit 数量, 不超过 maximum
+    pub fn usable(&self) -> usize {
+        self.usable_bits
+    }
 }
 
 /// 将bit编号 bit 分解为区域中的块编号 block_pos , 块内的组编号 bits64_pos 以及组内编号 inner_pos 的三元组
@@ -25,13 +25,21 @@
 use std::{
     collections::VecDeque,
     // sync::{Arc, Mutex},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
 };
 
 use lazy_static::*;
 use spin::Mutex; // https://docs.rs/spin/0.5.2/spin/struct.Mutex.html
 
-use super::{BlockDevice, BLOCK_CACHE_SIZE, BLOCK_SIZE};
+use super::{BlockDevice, CacheStats, BLOCK_CACHE_SIZE, BLOCK_SIZE};
+
+/// 块设备写块次数
+///
+/// sync 时只持有块缓存自身的锁, 不能再去获取 BLOCK_CACHE_MANAGER 的锁 (block_cache_sync_all 已经持有), 故单独使用原子计数
+static BLOCK_WRITES: AtomicUsize = AtomicUsize::new(0);
 
 /// Cached block inside memory
 pub struct BlockCache {
@@ -124,6 +132,7 @@ impl BlockCache {
     pub fn sync(&mut self) {
         if self.modified {
             self.block_device.write_block(self.block_id, &self.cache);
+            BLOCK_WRITES.fetch_add(1, Ordering::Relaxed);
             self.modified = false;
         }
     }
@@ -175,6 +184,12 @@ pub struct BlockCacheManager {
     /// 因此这里只是比较谨慎的留下一层保险.
     /// 注意:  VecDeque 中只以 block_id 作为标识的话, 同时读写不同设备的同一个 block 时会有冲突
     queue: VecDeque<(usize, Arc<Mutex<BlockCache>>)>,
+    /// 缓存命中次数
+    hits: usize,
+    /// 缓存未命中次数 (每次未命中都会触发一次 read_block)
+    misses: usize,
+    /// 缓存替换次数
+    evictions: usize,
 }
 
 /**
@@ -219,6 +234,9 @@ impl BlockCacheManager {
     pub fn new() -> Self {
         Self {
             queue: VecDeque::new(),
+            hits: 0,
+            misses: 0,
+            evictions: 0,
         }
     }
 
@@ -232,8 +250,10 @@ impl BlockCacheManager {
         // 遍历整个队列试图找到一个编号相同的块缓存,
         // 如果找到了, 会将块缓存管理器中保存的块缓存的引用复制一份并返回
         if let Some(pair) = self.queue.iter().find(|pair| pair.0 == block_id) {
+            self.hits += 1;
             Arc::clone(&pair.1)
         } else {
+            self.misses += 1;
             // 如果找不到, 此时必须将块从磁盘读入内存中的缓冲区.
             // 在实际读取之前, 需要判断管理器保存的块缓存数量是否已经达到了上限.
             // 如果达到了上限, 需要执行缓存替换算法, 丢掉某个块缓存并空出一个空位.
@@ -250,6 +270,7 @@ impl BlockCacheManager {
                     .find(|(_, pair)| Arc::strong_count(&pair.1) == 1)
                 {
                     self.queue.drain(idx..=idx); // 从队列中删除该块缓存, range: [idx, idx] == idx
+                    self.evictions += 1;
                 } else {
                     // 那么是否有可能出现队列已满且其中所有的块缓存都正在使用的情形呢?
                     // 事实上, 只要我们的上限 BLOCK_CACHE_SIZE 设置的足够大, 超过所有应用同时访问的块总数上限, 那么这种情况永远不会发生.
@@ -297,3 +318,14 @@ pub fn block_cache_sync_all() {
         block_cache.lock().sync();
     }
 }
+
+/// 获取块缓存层的累计计数
+pub fn block_cache_stats() -> CacheStats {
+    let manager = BLOCK_CACHE_MANAGER.lock();
+    CacheStats {
+        hits: manager.hits,
+        misses: manager.misses,
+        evictions: manager.evictions,
+        block_writes: BLOCK_WRITES.load(Ordering::Relaxed),
+    }
+}
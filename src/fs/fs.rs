@@ -8,9 +8,12 @@ use std::sync::Arc;
 
 use spin::Mutex;
 
+use super::merkle;
+
 use super::{
-    block_cache_sync_all, get_block_cache, Bitmap, BlockDevice, DiskInode, DiskInodeType, Inode,
-    SuperBlock, BLOCK_SIZE,
+    block_cache_stats, block_cache_sync_all, get_block_cache, AllocCounts, Allocator, Bitmap,
+    BlockDevice, CacheStats, DiskInode, DiskInodeType, FirstFit, Inode, MerkleHash, MerkleTables,
+    OpCounts, Reservation, Statistics, SuperBlock, BLOCK_SIZE,
 };
 
 /// 文件系统 (磁盘块管理器)
@@ -31,6 +34,24 @@ pub struct FileSystem {
     inode_area_start_block: u32,
     /// 数据区域起始块号
     data_area_start_block: u32,
+    /// 挂载时块缓存层的计数, 用于计算挂载以来的增量
+    mount_cache_stats: CacheStats,
+    /// 索引节点/数据块的分配与回收次数
+    alloc_counts: AllocCounts,
+    /// 文件操作次数, 由 Inode 在持有 fs 锁时更新
+    pub op_counts: OpCounts,
+    /// Merkle 模式下的节点表与叶子表, 未开启 Merkle 模式时为 None
+    merkle: Option<MerkleTables>,
+    /// 已经预留但尚未消耗的索引节点数量
+    reserved_inodes: usize,
+    /// 已经预留但尚未消耗的数据块数量
+    reserved_blocks: usize,
+    /// 空闲的索引节点数量, 第一次需要时才统计位图, 之后随分配/回收更新
+    free_inode_count: Option<usize>,
+    /// 空闲的数据块数量, 同上
+    free_block_count: Option<usize>,
+    /// 数据块的分配策略, 默认为 first-fit
+    allocator: Box<dyn Allocator>,
 }
 
 type DataBlock = [u8; BLOCK_SIZE];
@@ -93,10 +114,12 @@ impl FileSystem {
         let data_area_blocks = data_total_blocks - data_bitmap_blocks;
 
         // 初始化数据块位图
-        let data_bitmap = Bitmap::new(
+        // 位图的 bit 数量通常多于数据区域的块数, 只有前 data_area_blocks 个 bit 可以分配
+        let data_bitmap = Bitmap::with_usable(
             // inode_bitmap_blocks + inode_area_blocks = inode_total_blocks; + 1 is the super block
             (1 + inode_bitmap_blocks + inode_area_blocks) as usize,
             data_bitmap_blocks as usize,
+            data_area_blocks as usize,
         );
 
         // 初始化文件系统
@@ -109,6 +132,15 @@ impl FileSystem {
             inode_area_start_block: 1 + inode_bitmap_blocks,
             // 在 data_area 之前存放了 inode_bitmap, inode_area, data_bitmap, 故 data_area 的起始块号为 inode_bitmap_blocks + inode_area_blocks + 2
             data_area_start_block: 1 + inode_total_blocks + data_bitmap_blocks,
+            mount_cache_stats: CacheStats::default(),
+            alloc_counts: AllocCounts::default(),
+            op_counts: OpCounts::default(),
+            merkle: None,
+            reserved_inodes: 0,
+            reserved_blocks: 0,
+            free_inode_count: None,
+            free_block_count: None,
+            allocator: Box::new(FirstFit),
         };
 
         // 既然是创建文件系统, 第一次使用, 需要将块设备的前 total_blocks 个块清零
@@ -141,7 +173,7 @@ impl FileSystem {
         // 为根目录 "/" 创建一个 inode
         // 首先需要调用 alloc_inode 在 inode 位图中分配一个 inode ,
         // 由于这是第一次分配, 它的编号固定是 0 .
-        assert_eq!(fs.alloc_inode(), 0);
+        assert_eq!(fs.alloc_inode(), Some(0));
 
         // 将分配到的 inode 初始化为 fs 中的根目录,
         // 故需要调用 get_disk_inode_pos 来根据 inode 编号获取该 inode 所在的块的编号以及块内偏移,
@@ -156,6 +188,10 @@ impl FileSystem {
 
         block_cache_sync_all();
 
+        // 格式化时清零所有块产生的缓存计数, 以及分配根目录 inode 的计数都不计入统计
+        fs.mount_cache_stats = block_cache_stats();
+        fs.alloc_counts = AllocCounts::default();
+
         Arc::new(Mutex::new(fs))
     }
 
@@ -189,13 +225,120 @@ impl FileSystem {
     /// 以 bit 组(每组 64 bits)为单位进行遍历,
     /// 找到一个尚未被全部分配出去的组,
     /// 最后在里面分配一个 bit.
-    pub fn alloc_inode(&mut self) -> u32 {
-        self.inode_bitmap.alloc(&self.block_device).unwrap() as u32
+    ///
+    /// 没有空闲的索引节点, 或者剩下的都已经预留出去时返回 None
+    pub fn alloc_inode(&mut self) -> Option<u32> {
+        if self.reserved_inodes > 0 && self.free_inodes() <= self.reserved_inodes {
+            return None;
+        }
+        self.take_inode()
     }
 
     /// 分配数据块
-    pub fn alloc_data(&mut self) -> u32 {
-        self.data_bitmap.alloc(&self.block_device).unwrap() as u32 + self.data_area_start_block
+    ///
+    /// 没有空闲的数据块, 或者剩下的都已经预留出去时返回 None
+    pub fn alloc_data(&mut self) -> Option<u32> {
+        if self.reserved_blocks > 0 && self.free_data_blocks() <= self.reserved_blocks {
+            return None;
+        }
+        self.take_data()
+    }
+
+    /// 从位图中分配索引节点, 不检查预留
+    fn take_inode(&mut self) -> Option<u32> {
+        let inode_id = self.inode_bitmap.alloc(&self.block_device)? as u32;
+        self.alloc_counts.inode_allocs += 1;
+        if let Some(free) = self.free_inode_count.as_mut() {
+            *free -= 1;
+        }
+        Some(inode_id)
+    }
+
+    /// 按照分配策略分配数据块, 不检查预留
+    fn take_data(&mut self) -> Option<u32> {
+        let bit = self
+            .allocator
+            .alloc(&self.data_bitmap, &self.block_device)?;
+        self.alloc_counts.data_allocs += 1;
+        if let Some(free) = self.free_block_count.as_mut() {
+            *free -= 1;
+        }
+        Some(bit as u32 + self.data_area_start_block)
+    }
+
+    /// 更换数据块的分配策略, 在挂载 (create/open) 之后调用
+    pub fn set_allocator(&mut self, allocator: Box<dyn Allocator>) {
+        self.allocator = allocator;
+    }
+
+    /// 优先使用预留分配索引节点, 预留用完后从未预留的部分分配
+    pub fn alloc_reserved_inode(&mut self, reservation: &mut Reservation) -> Option<u32> {
+        if !reservation.take_inode() {
+            return self.alloc_inode();
+        }
+        // 预留时已经确认过有足够的空闲索引节点
+        self.reserved_inodes -= 1;
+        self.take_inode()
+    }
+
+    /// 优先使用预留分配数据块, 预留用完后从未预留的部分分配
+    pub fn alloc_reserved_data(&mut self, reservation: &mut Reservation) -> Option<u32> {
+        if !reservation.take_block() {
+            return self.alloc_data();
+        }
+        self.reserved_blocks -= 1;
+        self.take_data()
+    }
+
+    /// 空闲的索引节点数量 (包括已预留的)
+    ///
+    /// 第一次调用时统计位图, 之后随分配/回收更新, 不再重复读取位图
+    pub fn free_inodes(&mut self) -> usize {
+        if self.free_inode_count.is_none() {
+            self.free_inode_count = Some(
+                self.inode_bitmap.maximum() - self.inode_bitmap.count_allocated(&self.block_device),
+            );
+        }
+        self.free_inode_count.unwrap()
+    }
+
+    /// 空闲的数据块数量 (包括已预留的)
+    ///
+    /// 数据块位图能表示的 bit 数可能多于数据区域的块数, 因此以可分配的 bit 数 (data_area_blocks) 为准.
+    /// 与 free_inodes 一样只在第一次调用时统计位图
+    pub fn free_data_blocks(&mut self) -> usize {
+        if self.free_block_count.is_none() {
+            self.free_block_count = Some(
+                self.data_bitmap.usable() - self.data_bitmap.count_allocated(&self.block_device),
+            );
+        }
+        self.free_block_count.unwrap()
+    }
+
+    /// 预留 n 个索引节点, 空闲的索引节点不足时返回 None
+    pub fn reserve_inodes(fs: &Arc<Mutex<Self>>, n: usize) -> Option<Reservation> {
+        let mut guard = fs.lock();
+        if guard.free_inodes() < guard.reserved_inodes + n {
+            return None;
+        }
+        guard.reserved_inodes += n;
+        Some(Reservation::new(Arc::clone(fs), n, 0))
+    }
+
+    /// 预留 n 个数据块 (包括索引块), 空闲的数据块不足时返回 None
+    pub fn reserve_blocks(fs: &Arc<Mutex<Self>>, n: usize) -> Option<Reservation> {
+        let mut guard = fs.lock();
+        if guard.free_data_blocks() < guard.reserved_blocks + n {
+            return None;
+        }
+        guard.reserved_blocks += n;
+        Some(Reservation::new(Arc::clone(fs), 0, n))
+    }
+
+    /// 归还没有消耗的预留, 由 Reservation 在 drop 时调用
+    pub fn release(&mut self, inodes: usize, blocks: usize) {
+        self.reserved_inodes -= inodes;
+        self.reserved_blocks -= blocks;
     }
 
     /// 回收数据块
@@ -207,14 +350,19 @@ impl FileSystem {
                     *p = 0;
                 })
             });
-        self.data_bitmap.dealloc(
-            &self.block_device,
-            (block_id - self.data_area_start_block) as usize,
-        )
+        self.alloc_counts.data_deallocs += 1;
+        if let Some(tables) = self.merkle.as_ref() {
+            tables.reset_leaf(block_id);
+        }
+        if let Some(free) = self.free_block_count.as_mut() {
+            *free += 1;
+        }
+        let bit = (block_id - self.data_area_start_block) as usize;
+        self.data_bitmap.dealloc(&self.block_device, bit);
+        self.allocator.dealloc(bit);
     }
 
-    // maybe
-    #[allow(unused)]
+    /// 回收索引节点, 只回收位图中的 bit (调用者需要先回收它的数据块)
     pub fn dealloc_inode(&mut self, inode_id: u32) {
         // 由于一个块中可以存放 4 个索引节点, 因此相较于删除数据节点,
         // inode_id 对应的数据大小为 DirEntry 的大小, 也就是 128 字节
@@ -228,13 +376,19 @@ impl FileSystem {
         //             *p = 0;
         //         })
         //     });
-        self.inode_bitmap.dealloc(
-            &self.block_device,
-            (inode_id - self.inode_area_start_block) as usize,
-        )
+        self.alloc_counts.inode_deallocs += 1;
+        if let Some(free) = self.free_inode_count.as_mut() {
+            *free += 1;
+        }
+        // inode_id 就是索引节点位图中的 bit 编号
+        self.inode_bitmap
+            .dealloc(&self.block_device, inode_id as usize)
     }
 
     // 通过 open 方法可以从一个已写入了 fs 镜像的块设备上打开 fs
+    //
+    // open 只读取超级块: Bitmap 仅记录位图区域的起始块号与块数, 位图块本身
+    // 直到第一次 alloc/dealloc 时才会经由块缓存读入, 因此打开大镜像只做 ls/cat 时不会读取任何位图块
     pub fn open(block_device: Arc<dyn BlockDevice>) -> Arc<Mutex<Self>> {
         // 读超级块: 超级块的索引 id 为 0
         get_block_cache(0, Arc::clone(&block_device))
@@ -245,17 +399,32 @@ impl FileSystem {
                 let inode_total_blocks =
                     super_block.inode_bitmap_blocks + super_block.inode_area_blocks;
 
-                let fs = Self {
+                let mut fs = Self {
                     block_device,
                     inode_bitmap: Bitmap::new(1, super_block.inode_bitmap_blocks as usize),
-                    data_bitmap: Bitmap::new(
+                    data_bitmap: Bitmap::with_usable(
                         (1 + inode_total_blocks) as usize,
                         super_block.data_bitmap_blocks as usize,
+                        super_block.data_area_blocks as usize,
                     ),
                     inode_area_start_block: 1 + super_block.inode_bitmap_blocks,
                     // FIX: BUG for dealloc_data
                     data_area_start_block: 1 + inode_total_blocks + super_block.data_bitmap_blocks,
+                    mount_cache_stats: block_cache_stats(),
+                    alloc_counts: AllocCounts::default(),
+                    op_counts: OpCounts::default(),
+                    merkle: None,
+                    reserved_inodes: 0,
+                    reserved_blocks: 0,
+                    free_inode_count: None,
+                    free_block_count: None,
+                    allocator: Box::new(FirstFit),
                 };
+                if super_block.merkle_enabled != 0 && super_block.merkle_nodes != 0 {
+                    fs.merkle = Some(
+                        fs.load_merkle_tables(super_block.merkle_nodes, super_block.merkle_leaves),
+                    );
+                }
 
                 Arc::new(Mutex::new(fs))
             })
@@ -286,6 +455,225 @@ impl FileSystem {
         Inode::new(block_id, block_offset, Arc::clone(fs), block_device)
     }
 
+    /// 通过 inode 编号获取 Inode, 编号在索引节点位图中没有分配时返回 None
+    ///
+    /// 与 root_inode 一样, 预先查询 inode 在块设备中的位置再传给 Inode::new,
+    /// 使用者 (例如按编号查看/修复 inode 的工具) 不需要自己调用 get_disk_inode_pos
+    #[allow(unused)]
+    pub fn inode_by_id(fs: &Arc<Mutex<Self>>, inode_id: u32) -> Option<Inode> {
+        let (block_device, block_id, block_offset) = {
+            let fs = fs.lock();
+            if !fs
+                .inode_bitmap
+                .is_allocated(&fs.block_device, inode_id as usize)
+            {
+                return None;
+            }
+            let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
+            (Arc::clone(&fs.block_device), block_id, block_offset)
+        };
+        Some(Inode::new(
+            block_id,
+            block_offset,
+            Arc::clone(fs),
+            block_device,
+        ))
+    }
+
+    /// 获取自挂载以来的统计信息快照
+    ///
+    /// 汇总块缓存命中/替换, 块设备读写, 索引节点/数据块分配回收以及文件操作次数
+    pub fn statistics(&self) -> Statistics {
+        Statistics {
+            cache: block_cache_stats().since(&self.mount_cache_stats),
+            alloc: self.alloc_counts,
+            ops: self.op_counts,
+        }
+    }
+
+    /// 获取 Merkle 模式下超级块中保存的根哈希, 未开启 Merkle 模式时返回 None
+    pub fn merkle_mode(&self) -> Option<MerkleHash> {
+        get_block_cache(0, Arc::clone(&self.block_device))
+            .lock()
+            .read(0, |super_block: &SuperBlock| {
+                (super_block.merkle_enabled != 0).then_some(super_block.merkle_root)
+            })
+    }
+
+    /// 根据 inode 所在的块与块内偏移得到 inode 编号 (get_disk_inode_pos 的逆运算)
+    pub fn inode_id(&self, block_id: usize, block_offset: usize) -> u32 {
+        let inode_size = std::mem::size_of::<DiskInode>();
+        let inodes_per_block = BLOCK_SIZE / inode_size;
+        ((block_id - self.inode_area_start_block as usize) * inodes_per_block
+            + block_offset / inode_size) as u32
+    }
+
+    /// Merkle 模式下的节点表与叶子表
+    pub fn merkle_tables(&self) -> Option<&MerkleTables> {
+        self.merkle.as_ref()
+    }
+
+    /// 节点表与叶子表 (包括索引块) 占用的数据块数, 未开启 Merkle 模式时为 0
+    pub fn merkle_table_blocks(&self) -> usize {
+        self.merkle.as_ref().map_or(0, |tables| {
+            [tables.node_inode, tables.leaf_inode]
+                .iter()
+                .map(|&inode_id| {
+                    let (block_id, block_offset) = self.get_disk_inode_pos(inode_id);
+                    get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+                        .lock()
+                        .read(block_offset, |disk_inode: &DiskInode| {
+                            DiskInode::total_blocks(disk_inode.alloc_size) as usize
+                        })
+                })
+                .sum()
+        })
+    }
+
+    /// 开启 Merkle 模式: 分配节点表与叶子表, 从磁盘内容计算整棵树的哈希, 即信任当前的目录树
+    ///
+    /// 已经开启时重新计算, 之前标记为篡改的节点也随之被信任. 空闲的数据块或 inode 不足时返回 None
+    pub fn merkle_on(&mut self) -> Option<MerkleHash> {
+        self.merkle_off();
+        let node_inode =
+            self.alloc_table(merkle::node_table_blocks(self.inode_bitmap.maximum()))?;
+        let leaf_inode =
+            match self.alloc_table(merkle::leaf_table_blocks(self.data_bitmap.usable())) {
+                Some(leaf_inode) => leaf_inode,
+                None => {
+                    self.free_table(node_inode);
+                    return None;
+                }
+            };
+        let tables = self.load_merkle_tables(node_inode, leaf_inode);
+        let root = merkle::walk(self, Some(&tables), true, (0, 0), "/", &mut Vec::new());
+        self.merkle = Some(tables);
+        get_block_cache(0, Arc::clone(&self.block_device))
+            .lock()
+            .modify(0, |super_block: &mut SuperBlock| {
+                super_block.merkle_enabled = 1;
+                super_block.merkle_root = root;
+                super_block.merkle_nodes = node_inode;
+                super_block.merkle_leaves = leaf_inode;
+            });
+        block_cache_sync_all();
+        Some(root)
+    }
+
+    /// 关闭 Merkle 模式, 回收节点表与叶子表
+    pub fn merkle_off(&mut self) {
+        if let Some(tables) = self.merkle.take() {
+            self.free_table(tables.node_inode);
+            self.free_table(tables.leaf_inode);
+        }
+        get_block_cache(0, Arc::clone(&self.block_device))
+            .lock()
+            .modify(0, |super_block: &mut SuperBlock| {
+                super_block.merkle_enabled = 0;
+                super_block.merkle_root = [0; 32];
+                super_block.merkle_nodes = 0;
+                super_block.merkle_leaves = 0;
+            });
+        block_cache_sync_all();
+    }
+
+    /// 从磁盘内容重新计算根哈希, 返回 (超级块中保存的根哈希, 计算得到的根哈希, 与节点表不一致的路径)
+    ///
+    /// 只做比较, 不会修改保存的根哈希. 未开启 Merkle 模式时返回 None
+    pub fn merkle_verify(&self) -> Option<(MerkleHash, MerkleHash, Vec<String>)> {
+        let stored = self.merkle_mode()?;
+        let mut mismatched = Vec::new();
+        let root = merkle::walk(
+            self,
+            self.merkle.as_ref(),
+            false,
+            (0, 0),
+            "/",
+            &mut mismatched,
+        );
+        Some((stored, root, mismatched))
+    }
+
+    /// inode_id 在节点表中的哈希由 old 更新之后, 沿父目录更新到根, 并将新的根哈希写入超级块
+    ///
+    /// 需要读取父目录的 DiskInode, 调用时不能持有任何 inode 所在块的锁
+    pub fn merkle_propagate(&self, inode_id: u32, old: MerkleHash) {
+        if let Some(tables) = self.merkle.as_ref() {
+            let root = tables.propagate(self, inode_id, old);
+            get_block_cache(0, Arc::clone(&self.block_device))
+                .lock()
+                .modify(0, |super_block: &mut SuperBlock| {
+                    super_block.merkle_root = root;
+                });
+        }
+    }
+
+    /// 根据两个隐藏文件的 inode 编号读入节点表与叶子表
+    fn load_merkle_tables(&self, node_inode: u32, leaf_inode: u32) -> MerkleTables {
+        let read = |inode_id: u32| -> Vec<u32> {
+            let (block_id, block_offset) = self.get_disk_inode_pos(inode_id);
+            get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+                .lock()
+                .read(block_offset, |disk_inode: &DiskInode| {
+                    (0..disk_inode.data_blocks())
+                        .map(|inner_id| disk_inode.get_block_id(inner_id, &self.block_device))
+                        .collect()
+                })
+        };
+        MerkleTables::new(
+            (node_inode, read(node_inode)),
+            (leaf_inode, read(leaf_inode)),
+            self.data_area_start_block,
+            Arc::clone(&self.block_device),
+        )
+    }
+
+    /// 分配一个不链接到目录树中的隐藏文件, 内容为 blocks 个清零的数据块, 返回其 inode 编号
+    fn alloc_table(&mut self, blocks: usize) -> Option<u32> {
+        let inode_id = self.alloc_inode()?;
+        let size = (blocks * BLOCK_SIZE) as u32;
+        let mut v: Vec<u32> = Vec::new();
+        for _ in 0..DiskInode::total_blocks(size) {
+            match self.alloc_data() {
+                Some(block_id) => v.push(block_id),
+                None => {
+                    for block_id in v {
+                        self.dealloc_data(block_id);
+                    }
+                    self.dealloc_inode(inode_id);
+                    return None;
+                }
+            }
+        }
+        for &block_id in v.iter() {
+            get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+                .lock()
+                .modify(0, |data_block: &mut DataBlock| data_block.fill(0));
+        }
+        let (block_id, block_offset) = self.get_disk_inode_pos(inode_id);
+        get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+            .lock()
+            .modify(block_offset, |disk_inode: &mut DiskInode| {
+                disk_inode.initialize(DiskInodeType::File);
+                disk_inode.increase_size(size, v, &self.block_device);
+            });
+        Some(inode_id)
+    }
+
+    /// 回收 alloc_table 分配的隐藏文件
+    fn free_table(&mut self, inode_id: u32) {
+        let (block_id, block_offset) = self.get_disk_inode_pos(inode_id);
+        let blocks = get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+            .lock()
+            .modify(block_offset, |disk_inode: &mut DiskInode| {
+                disk_inode.clear_size(&self.block_device)
+            });
+        for block_id in blocks {
+            self.dealloc_data(block_id);
+        }
+        self.dealloc_inode(inode_id);
+    }
+
     // TODO: dealloc_inode
     // 对于目录项所使用的块难以清理, 因为一个块中可以存放 4 个目录项, 删除一个文件不能保证使用的块没有目录项了
     // 可能需要对数据结构进行修改, 比如维护块内编号
@@ -22,11 +22,13 @@
 use std::{
     fmt::{Debug, Formatter, Result},
     sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
 };
 
 use super::{
-    get_block_cache, BlockDevice, BLOCK_SIZE, DIRENT_SIZE, EAZY_FS_MAGIC, INDIRECT1_BOUND,
-    INODE_DIRECT_COUNT, INODE_INDIRECT1_COUNT, INODE_INDIRECT2_COUNT, NAME_LENGTH_LIMIT,
+    get_block_cache, merkle_hex, BlockDevice, MerkleHash, BLOCK_SIZE, DIRENT_SIZE, EAZY_FS_MAGIC,
+    INDIRECT1_BOUND, INODE_DIRECT_COUNT, INODE_INDIRECT1_COUNT, INODE_INDIRECT2_COUNT,
+    MAX_FILE_SIZE, NAME_LENGTH_LIMIT,
 };
 
 #[repr(C)]
@@ -37,6 +39,14 @@ pub struct SuperBlock {
     pub inode_area_blocks: u32,
     pub data_bitmap_blocks: u32,
     pub data_area_blocks: u32,
+    /// 是否开启 Merkle 模式 (非 0 为开启)
+    pub merkle_enabled: u32,
+    /// Merkle 模式下整棵目录树的根哈希
+    pub merkle_root: MerkleHash,
+    /// Merkle 模式下节点表所在隐藏文件的 inode 编号
+    pub merkle_nodes: u32,
+    /// Merkle 模式下叶子表所在隐藏文件的 inode 编号
+    pub merkle_leaves: u32,
 }
 
 impl Debug for SuperBlock {
@@ -48,6 +58,10 @@ impl Debug for SuperBlock {
             .field("inode_area_blocks", &self.inode_area_blocks)
             .field("data_bitmap_blocks", &self.data_bitmap_blocks)
             .field("data_area_blocks", &self.data_area_blocks)
+            .field("merkle_enabled", &self.merkle_enabled)
+            .field("merkle_root", &merkle_hex(&self.merkle_root))
+            .field("merkle_nodes", &self.merkle_nodes)
+            .field("merkle_leaves", &self.merkle_leaves)
             .finish()
     }
 }
@@ -72,6 +86,10 @@ impl SuperBlock {
             inode_area_blocks,
             data_bitmap_blocks,
             data_area_blocks,
+            merkle_enabled: 0,
+            merkle_root: [0; 32],
+            merkle_nodes: 0,
+            merkle_leaves: 0,
         };
     }
 
@@ -96,7 +114,7 @@ type DataBlock = [u8; BLOCK_SIZE]; // size = 512B
 
 /// 每个 文件/目录 在磁盘上均以一个 DiskInode 的形式存储
 ///
-/// 由于字节对齐, DiskInode 大小为 (1 + 28 + 1 + 1) * 4 + 4(字节对齐) = 128 B
+/// 由于字节对齐, DiskInode 大小为 (1 + 1 + 25 + 1 + 1 + 2) * 4 + 4(字节对齐) = 128 B
 ///
 /// 为了充分利用空间, 将 DiskInode 的大小设置为 128 字节, 每个块正好能够容纳 4 个 DiskInode
 //
@@ -131,17 +149,35 @@ pub struct DiskInode {
     /// 一个不同的一级索引块, 这些一级索引块也位于数据块区域中
     /// . 因此, 通过二级间接索引最多能够索引 128 * 64KB = 8MB 的内容
     pub indirect2: u32,
+    /// 最近访问时间 (秒, UNIX 时间戳)
+    pub atime: u32,
+    /// 最近修改时间 (秒, UNIX 时间戳)
+    pub mtime: u32,
     /// 索引节点的类型 DiskInodeType, 目前仅支持文件 File 和目录 Directory 两种类型
     pub type_: DiskInodeType,
 }
 
+// 每个块正好容纳 4 个 DiskInode, 修改 DiskInode 的字段时需要保证其大小不变
+const _: () = assert!(std::mem::size_of::<DiskInode>() == 128);
+
+/// 当前时间 (秒, UNIX 时间戳)
+pub fn timestamp_now() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as u32)
+        .unwrap_or(0)
+}
+
 impl DiskInode {
+    /// 初始化 DiskInode, atime/mtime 均设置为当前时间
     pub fn initialize(&mut self, type_: DiskInodeType) {
         self.size = 0;
         self.alloc_size = 0;
         self.direct.iter_mut().for_each(|x| *x = 0);
         self.indirect1 = 0;
         self.indirect2 = 0;
+        self.atime = timestamp_now();
+        self.mtime = self.atime;
         self.type_ = type_;
     }
 
@@ -228,6 +264,21 @@ impl DiskInode {
         total as u32
     }
 
+    /// 最多占用 blocks 个数据块 (包括索引块) 的文件的最大大小, 不超过 MAX_FILE_SIZE
+    pub fn max_size(blocks: u32) -> u32 {
+        // total_blocks 随数据块数单调递增, 二分查找最多能容纳的数据块数
+        let (mut low, mut high) = (0, (MAX_FILE_SIZE / BLOCK_SIZE) as u32);
+        while low < high {
+            let mid = (low + high).div_ceil(2);
+            if Self::total_blocks(mid * BLOCK_SIZE as u32) <= blocks {
+                low = mid;
+            } else {
+                high = mid - 1;
+            }
+        }
+        low * BLOCK_SIZE as u32
+    }
+
     /// 计算将一个 DiskInode 的 size 扩容到 new_size 需要额外多少个数据和索引块
     pub fn blocks_num_needed(&self, new_size: u32) -> u32 {
         assert!(new_size >= self.alloc_size);
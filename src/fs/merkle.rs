@@ -0,0 +1,430 @@
+//! 整棵目录树的 Merkle 树校验
+//!
+//! 叶子节点是每个数据块 (BLOCK_SIZE 字节) 的哈希, 文件节点是 文件大小 + 其所有数据块哈希 的哈希,
+//! 目录节点是其所有目录项 (按名称排序的 名称 + 子节点哈希) 的哈希, 最终得到根目录的哈希.
+//!
+//! 开启 Merkle 模式后, 根哈希保存在超级块中, 每个数据块的哈希 (叶子表) 以及每个 inode 的哈希和父目录 (节点表)
+//! 保存在两个不链接到目录树中的隐藏文件里, 它们的 inode 编号同样记录在超级块中.
+//! 写入/创建/删除/改名时只重新计算被修改的数据块, 再沿节点表中的父目录一路更新到根哈希,
+//! 不需要重新读取其他文件的内容.
+//!
+//! 更新一个节点之前先检查它原来的哈希与表中记录的一致. 镜像在 Merkle 模式之外被修改过时检查失败,
+//! 该节点被标记为 [`POISON`], 之后的修改不会把篡改 "洗白", verify --merkle 会一直报告不一致,
+//! 直到再次 merkle on 重新信任当前的目录树.
+
+use std::sync::Arc;
+
+use lazy_static::*;
+use sha2::{Digest, Sha256};
+
+use super::{
+    get_block_cache, BlockDevice, DirEntry, DiskInode, FileSystem, BLOCK_SIZE, DIRENT_SIZE,
+};
+
+/// SHA-256 哈希值
+pub type MerkleHash = [u8; 32];
+
+/// 节点类型前缀, 避免文件节点与目录节点的哈希输入相互混淆
+const FILE_NODE: u8 = 0;
+const DIR_NODE: u8 = 1;
+
+/// 检查失败 (被篡改过) 的节点的哈希, 从磁盘内容计算出的哈希不会等于它
+pub const POISON: MerkleHash = [0xff; 32];
+
+/// 节点表中每一项的大小: 哈希 + 父目录的 inode 编号, 每项不跨块
+const NODE_SIZE: usize = 36;
+const NODES_PER_BLOCK: usize = BLOCK_SIZE / NODE_SIZE;
+/// 叶子表中每个块存放的哈希数量
+const LEAVES_PER_BLOCK: usize = BLOCK_SIZE / 32;
+
+lazy_static! {
+    /// 全零数据块的哈希. 叶子表中全零的一项表示全零的数据块, 新分配的叶子表不需要初始化
+    static ref ZERO_LEAF: MerkleHash = Sha256::digest([0u8; BLOCK_SIZE]).into();
+}
+
+/// 节点表需要的块数
+pub fn node_table_blocks(inodes: usize) -> usize {
+    inodes.div_ceil(NODES_PER_BLOCK)
+}
+
+/// 叶子表需要的块数
+pub fn leaf_table_blocks(data_blocks: usize) -> usize {
+    data_blocks.div_ceil(LEAVES_PER_BLOCK)
+}
+
+/// 数据块 (块设备上的编号) 的哈希
+fn block_hash(block_id: u32, block_device: &Arc<dyn BlockDevice>) -> MerkleHash {
+    get_block_cache(block_id as usize, Arc::clone(block_device))
+        .lock()
+        .read(0, |data: &[u8; BLOCK_SIZE]| Sha256::digest(data).into())
+}
+
+/// 文件节点的哈希, leaves 为前 size 字节所在的数据块的哈希
+fn file_node(size: u32, leaves: impl Iterator<Item = MerkleHash>) -> MerkleHash {
+    let mut hasher = Sha256::new();
+    hasher.update([FILE_NODE]);
+    hasher.update(size.to_le_bytes());
+    for leaf in leaves {
+        hasher.update(leaf);
+    }
+    hasher.finalize().into()
+}
+
+/// 目录节点的哈希, entries 为 (名称, 子节点哈希)
+fn dir_node(mut entries: Vec<(String, MerkleHash)>) -> MerkleHash {
+    entries.sort();
+    let mut hasher = Sha256::new();
+    hasher.update([DIR_NODE]);
+    for (name, hash) in entries {
+        hasher.update([name.len() as u8]);
+        hasher.update(name.as_bytes());
+        hasher.update(hash);
+    }
+    hasher.finalize().into()
+}
+
+/// 新建的空文件/空目录的哈希
+pub fn empty_hash(is_dir: bool) -> MerkleHash {
+    if is_dir {
+        dir_node(Vec::new())
+    } else {
+        file_node(0, std::iter::empty())
+    }
+}
+
+/// 前 size 字节占用的数据块数
+fn size_blocks(size: u32) -> u32 {
+    (size as usize).div_ceil(BLOCK_SIZE) as u32
+}
+
+/// 读取目录的所有目录项 (名称, inode 编号)
+fn dir_entries(disk_inode: &DiskInode, block_device: &Arc<dyn BlockDevice>) -> Vec<(String, u32)> {
+    let file_count = (disk_inode.size as usize) / DIRENT_SIZE;
+    let mut dir_entry = DirEntry::create_empty();
+    (0..file_count)
+        .map(|i| {
+            disk_inode.read_at(i * DIRENT_SIZE, dir_entry.as_bytes_mut(), block_device);
+            (dir_entry.name().to_string(), dir_entry.inode_id())
+        })
+        .collect()
+}
+
+/// 将 inode_id 的 DiskInode 交给 f 读取
+fn read_inode<V>(fs: &FileSystem, inode_id: u32, f: impl FnOnce(&DiskInode) -> V) -> V {
+    let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
+    get_block_cache(block_id as usize, Arc::clone(&fs.block_device))
+        .lock()
+        .read(block_offset, f)
+}
+
+/// Merkle 模式下持久化的节点表与叶子表
+///
+/// 两个表所在的数据块在挂载时读入内存, 访问表时只需要获取这些数据块的锁,
+/// 因此可以在持有某个 inode 所在块的锁时 (modify_disk_inode 的闭包中) 读写表
+pub struct MerkleTables {
+    /// 节点表所在隐藏文件的 inode 编号
+    pub node_inode: u32,
+    /// 叶子表所在隐藏文件的 inode 编号
+    pub leaf_inode: u32,
+    node_blocks: Vec<u32>,
+    leaf_blocks: Vec<u32>,
+    /// 叶子表以数据块在数据区域中的编号为下标
+    data_area_start_block: u32,
+    block_device: Arc<dyn BlockDevice>,
+}
+
+impl MerkleTables {
+    /// node 与 leaf 分别为 (隐藏文件的 inode 编号, 表所在的数据块)
+    pub fn new(
+        (node_inode, node_blocks): (u32, Vec<u32>),
+        (leaf_inode, leaf_blocks): (u32, Vec<u32>),
+        data_area_start_block: u32,
+        block_device: Arc<dyn BlockDevice>,
+    ) -> Self {
+        Self {
+            node_inode,
+            leaf_inode,
+            node_blocks,
+            leaf_blocks,
+            data_area_start_block,
+            block_device,
+        }
+    }
+
+    /// 节点表中 inode_id 的 (哈希, 父目录)
+    pub fn node(&self, inode_id: u32) -> (MerkleHash, u32) {
+        let inode_id = inode_id as usize;
+        let offset = (inode_id % NODES_PER_BLOCK) * NODE_SIZE;
+        get_block_cache(
+            self.node_blocks[inode_id / NODES_PER_BLOCK] as usize,
+            Arc::clone(&self.block_device),
+        )
+        .lock()
+        .read(offset, |node: &[u8; NODE_SIZE]| {
+            let mut hash = [0u8; 32];
+            hash.copy_from_slice(&node[..32]);
+            (hash, u32::from_le_bytes(node[32..].try_into().unwrap()))
+        })
+    }
+
+    pub fn set_node(&self, inode_id: u32, hash: MerkleHash, parent: u32) {
+        let inode_id = inode_id as usize;
+        let offset = (inode_id % NODES_PER_BLOCK) * NODE_SIZE;
+        get_block_cache(
+            self.node_blocks[inode_id / NODES_PER_BLOCK] as usize,
+            Arc::clone(&self.block_device),
+        )
+        .lock()
+        .modify(offset, |node: &mut [u8; NODE_SIZE]| {
+            node[..32].copy_from_slice(&hash);
+            node[32..].copy_from_slice(&parent.to_le_bytes());
+        });
+    }
+
+    /// 叶子在叶子表中的位置 (所在的块, 块内偏移), 不在数据区域中的块没有叶子
+    fn leaf_pos(&self, block_id: u32) -> Option<(u32, usize)> {
+        let index = block_id.checked_sub(self.data_area_start_block)? as usize;
+        let table_block = *self.leaf_blocks.get(index / LEAVES_PER_BLOCK)?;
+        Some((table_block, (index % LEAVES_PER_BLOCK) * 32))
+    }
+
+    /// 数据块 (块设备上的编号) 的叶子
+    pub fn leaf(&self, block_id: u32) -> MerkleHash {
+        let (table_block, offset) = match self.leaf_pos(block_id) {
+            Some(pos) => pos,
+            // 索引被篡改成了数据区域之外的块
+            None => return POISON,
+        };
+        let leaf = get_block_cache(table_block as usize, Arc::clone(&self.block_device))
+            .lock()
+            .read(offset, |leaf: &MerkleHash| *leaf);
+        if leaf == [0; 32] {
+            *ZERO_LEAF
+        } else {
+            leaf
+        }
+    }
+
+    /// 回收的数据块会被清零, 其叶子也随之清零
+    pub fn reset_leaf(&self, block_id: u32) {
+        self.set_leaf(block_id, *ZERO_LEAF);
+    }
+
+    pub fn set_leaf(&self, block_id: u32, hash: MerkleHash) {
+        if let Some((table_block, offset)) = self.leaf_pos(block_id) {
+            let hash = if hash == *ZERO_LEAF { [0; 32] } else { hash };
+            get_block_cache(table_block as usize, Arc::clone(&self.block_device))
+                .lock()
+                .modify(offset, |leaf: &mut MerkleHash| *leaf = hash);
+        }
+    }
+
+    /// 根据叶子表计算文件节点的哈希
+    fn file_hash(&self, disk_inode: &DiskInode) -> MerkleHash {
+        file_node(
+            disk_inode.size,
+            (0..size_blocks(disk_inode.size))
+                .map(|inner_id| self.leaf(disk_inode.get_block_id(inner_id, &self.block_device))),
+        )
+    }
+
+    /// 根据目录项和节点表计算目录节点的哈希, replace 为 Some((inode_id, hash)) 时以 hash 代替该子节点在表中的哈希
+    fn dir_hash(&self, disk_inode: &DiskInode, replace: Option<(u32, MerkleHash)>) -> MerkleHash {
+        dir_node(
+            dir_entries(disk_inode, &self.block_device)
+                .into_iter()
+                .map(|(name, inode_id)| match replace {
+                    Some((id, hash)) if id == inode_id => (name, hash),
+                    _ => (name, self.node(inode_id).0),
+                })
+                .collect(),
+        )
+    }
+
+    /// 根据表计算 inode 当前的哈希
+    fn inode_hash(&self, disk_inode: &DiskInode) -> MerkleHash {
+        if disk_inode.is_dir() {
+            self.dir_hash(disk_inode, None)
+        } else {
+            self.file_hash(disk_inode)
+        }
+    }
+
+    /// 修改 inode 之前调用: 返回节点表中原来的哈希, 以及它是否与表中的叶子/子节点一致
+    pub fn check(&self, inode_id: u32, disk_inode: &DiskInode) -> (MerkleHash, bool) {
+        let (stored, _) = self.node(inode_id);
+        (stored, self.inode_hash(disk_inode) == stored)
+    }
+
+    /// 写入文件的 [offset, offset + len) 之前调用, 除了 check 之外,
+    /// 只被部分覆盖的数据块原来的内容也需要与叶子一致, 否则写入后的叶子会把篡改过的内容包括进去
+    pub fn check_write(
+        &self,
+        inode_id: u32,
+        disk_inode: &DiskInode,
+        offset: usize,
+        len: usize,
+    ) -> (MerkleHash, bool) {
+        let (stored, mut ok) = self.check(inode_id, disk_inode);
+        if len > 0 {
+            let first = offset / BLOCK_SIZE;
+            let last = (offset + len - 1) / BLOCK_SIZE;
+            for (inner_id, partial) in [
+                (first, !offset.is_multiple_of(BLOCK_SIZE)),
+                (last, !(offset + len).is_multiple_of(BLOCK_SIZE)),
+            ] {
+                if partial && inner_id < disk_inode.data_blocks() as usize {
+                    let block_id = disk_inode.get_block_id(inner_id as u32, &self.block_device);
+                    ok &= block_hash(block_id, &self.block_device) == self.leaf(block_id);
+                }
+            }
+        }
+        (stored, ok)
+    }
+
+    /// 写入文件的 [offset, offset + len) 之后调用: 更新被写入的数据块的叶子, 再更新文件节点
+    ///
+    /// 新分配的数据块都是清零过的, 叶子表中对应的项也已经清零, 不需要更新
+    pub fn update_write(
+        &self,
+        inode_id: u32,
+        disk_inode: &DiskInode,
+        offset: usize,
+        len: usize,
+        ok: bool,
+    ) {
+        for inner_id in offset / BLOCK_SIZE..(offset + len).div_ceil(BLOCK_SIZE) {
+            let block_id = disk_inode.get_block_id(inner_id as u32, &self.block_device);
+            self.set_leaf(block_id, block_hash(block_id, &self.block_device));
+        }
+        self.update(inode_id, disk_inode, ok);
+    }
+
+    /// 修改 inode 之后调用: check 通过时根据表重新计算哈希, 否则标记为 POISON
+    pub fn update(&self, inode_id: u32, disk_inode: &DiskInode, ok: bool) {
+        let hash = if ok {
+            self.inode_hash(disk_inode)
+        } else {
+            POISON
+        };
+        let (_, parent) = self.node(inode_id);
+        self.set_node(inode_id, hash, parent);
+    }
+
+    /// 子节点 child 的哈希由 old 变为表中的新值之后, 更新其父目录 parent 的哈希, 返回父目录原来的哈希
+    ///
+    /// 子节点被标记为 POISON 时父目录也标记为 POISON, 之后删除该子节点也不能让篡改被接受
+    fn update_parent(
+        &self,
+        parent: u32,
+        parent_disk_inode: &DiskInode,
+        child: u32,
+        old: MerkleHash,
+    ) -> MerkleHash {
+        let (stored, grandparent) = self.node(parent);
+        let consistent = self.dir_hash(parent_disk_inode, Some((child, old))) == stored;
+        let hash = if consistent && self.node(child).0 != POISON {
+            self.dir_hash(parent_disk_inode, None)
+        } else {
+            POISON
+        };
+        self.set_node(parent, hash, grandparent);
+        stored
+    }
+
+    /// 节点 inode_id 的哈希由 old 变为表中的新值之后, 沿父目录一路更新到根目录, 返回新的根哈希
+    ///
+    /// 需要读取各个父目录的 DiskInode, 因此不能在持有 inode 所在块的锁时调用
+    pub fn propagate(&self, fs: &FileSystem, mut inode_id: u32, mut old: MerkleHash) -> MerkleHash {
+        // 父目录的链条不会超过 inode 的数量, 超过说明节点表被篡改成了环
+        for _ in 0..fs.inode_bitmap.maximum() {
+            if inode_id == 0 {
+                return self.node(0).0;
+            }
+            let (_, parent) = self.node(inode_id);
+            old = read_inode(fs, parent, |disk_inode| {
+                self.update_parent(parent, disk_inode, inode_id, old)
+            });
+            inode_id = parent;
+        }
+        let (_, parent) = self.node(0);
+        self.set_node(0, POISON, parent);
+        POISON
+    }
+}
+
+/// 从磁盘内容计算以 inode_id 为根的子树的哈希
+///
+/// tables 为 Some 时同时比较节点表: build 为 true 时将叶子和节点写入表中 (merkle on),
+/// 否则将哈希与表中不一致的路径记录到 mismatched 中 (verify, 目录只在其子节点都一致时才记录)
+pub fn walk(
+    fs: &FileSystem,
+    tables: Option<&MerkleTables>,
+    build: bool,
+    (inode_id, parent): (u32, u32),
+    path: &str,
+    mismatched: &mut Vec<String>,
+) -> MerkleHash {
+    let block_device = &fs.block_device;
+    let (is_dir, size, blocks, entries) = read_inode(fs, inode_id, |disk_inode| {
+        let blocks: Vec<u32> = (0..disk_inode.data_blocks())
+            .map(|inner_id| disk_inode.get_block_id(inner_id, block_device))
+            .collect();
+        let entries = if disk_inode.is_dir() {
+            dir_entries(disk_inode, block_device)
+        } else {
+            Vec::new()
+        };
+        (disk_inode.is_dir(), disk_inode.size, blocks, entries)
+    });
+    let reported = mismatched.len();
+    let hash = if is_dir {
+        dir_node(
+            entries
+                .into_iter()
+                .map(|(name, child)| {
+                    let child_path = format!("{}{}/", path, name);
+                    let hash = walk(
+                        fs,
+                        tables,
+                        build,
+                        (child, inode_id),
+                        &child_path,
+                        mismatched,
+                    );
+                    (name, hash)
+                })
+                .collect(),
+        )
+    } else {
+        // 已分配但超出 size 的数据块不属于文件节点, 但 merkle on 时同样记录其叶子, 以便之后文件在原地变大
+        let leaves: Vec<MerkleHash> = blocks
+            .iter()
+            .map(|&block_id| block_hash(block_id, block_device))
+            .collect();
+        if let (true, Some(tables)) = (build, tables) {
+            for (&block_id, &leaf) in blocks.iter().zip(leaves.iter()) {
+                tables.set_leaf(block_id, leaf);
+            }
+        }
+        file_node(size, leaves.into_iter().take(size_blocks(size) as usize))
+    };
+    if let Some(tables) = tables {
+        if build {
+            tables.set_node(inode_id, hash, parent);
+        } else if tables.node(inode_id).0 != hash && mismatched.len() == reported {
+            let path = if is_dir {
+                path
+            } else {
+                path.trim_end_matches('/')
+            };
+            mismatched.push(path.to_string());
+        }
+    }
+    hash
+}
+
+/// 以十六进制显示哈希值
+pub fn merkle_hex(hash: &MerkleHash) -> String {
+    hash.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
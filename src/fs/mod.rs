@@ -1,8 +1,13 @@
+mod allocator;
 mod bitmap;
 mod block_cache;
 mod block_dev;
 mod fs;
 mod layout;
+mod merkle;
+mod reservation;
+mod stats;
+mod usage;
 mod vfs;
 
 extern crate log;
@@ -12,9 +17,11 @@ pub const BLOCK_SIZE: usize = 512;
 /// 为了避免在块缓存上浪费过多内存, 内存中同时只能驻留有限个磁盘块的缓冲区
 pub const BLOCK_CACHE_SIZE: usize = 16;
 /// Magic number for sanity check
-pub const EAZY_FS_MAGIC: u32 = 0x3b800001;
+///
+/// 磁盘布局发生变化时递增, 避免用新的布局去解析旧的镜像 (0x3b800002: DiskInode 增加了 atime/mtime)
+pub const EAZY_FS_MAGIC: u32 = 0x3b800002;
 /// The max number of direct inodes
-pub const INODE_DIRECT_COUNT: usize = 27; // note: 可根据元数据情况修改
+pub const INODE_DIRECT_COUNT: usize = 25; // note: 可根据元数据情况修改 (让出 2 个 u32 存放 atime/mtime)
 /// The max length of inode name
 pub const NAME_LENGTH_LIMIT: usize = 27;
 /// The max number of indirect1 inodes
@@ -26,16 +33,22 @@ pub const DIRECT_BOUND: usize = INODE_DIRECT_COUNT;
 /// The upper bound of indirect1 inode index
 pub const INDIRECT1_BOUND: usize = DIRECT_BOUND + INODE_INDIRECT1_COUNT;
 /// The upper bound of indirect2 inode index
-#[allow(unused)]
 pub const INDIRECT2_BOUND: usize = INDIRECT1_BOUND + INODE_INDIRECT2_COUNT;
+/// 单个文件的最大字节数 (受二级索引的范围限制)
+pub const MAX_FILE_SIZE: usize = INDIRECT2_BOUND * BLOCK_SIZE;
 /// 块的 bit 数量
 pub const BLOCK_BITS: usize = BLOCK_SIZE * 8;
 /// 目录项的大小
 pub const DIRENT_SIZE: usize = 32;
 
+pub use allocator::{allocator_by_name, Allocator, FirstFit};
 pub use bitmap::Bitmap;
-pub use block_cache::{block_cache_sync_all, get_block_cache};
+pub use block_cache::{block_cache_stats, block_cache_sync_all, get_block_cache};
 pub use block_dev::BlockDevice;
 pub use fs::FileSystem;
 pub use layout::*;
+pub use merkle::{merkle_hex, MerkleHash, MerkleTables};
+pub use reservation::Reservation;
+pub use stats::{AllocCounts, CacheStats, OpCounts, Statistics};
+pub use usage::Usage;
 pub use vfs::Inode;
@@ -0,0 +1,73 @@
+//! 索引节点/数据块预留
+//!
+//! 上层 (例如导入整棵目录树的 set) 可以先通过 [`FileSystem::reserve_inodes`] / [`FileSystem::reserve_blocks`]
+//! 预留所需的空间, 空间不足时在写入任何内容之前就失败, 避免镜像中只导入了一半.
+//!
+//! 预留得到的 [`Reservation`] 交给 Inode::create_reserved / Inode::write_reserved 消耗,
+//! 没有用完的部分在 Reservation 被 drop 时归还.
+
+use std::sync::Arc;
+
+use spin::Mutex;
+
+use super::FileSystem;
+
+/// 预留凭证, 记录尚未消耗的索引节点和数据块数量
+pub struct Reservation {
+    fs: Arc<Mutex<FileSystem>>,
+    inodes: usize,
+    blocks: usize,
+}
+
+impl Reservation {
+    pub(super) fn new(fs: Arc<Mutex<FileSystem>>, inodes: usize, blocks: usize) -> Self {
+        Self { fs, inodes, blocks }
+    }
+
+    /// 剩余的索引节点数量
+    #[allow(unused)]
+    pub fn inodes(&self) -> usize {
+        self.inodes
+    }
+
+    /// 剩余的数据块数量
+    #[allow(unused)]
+    pub fn blocks(&self) -> usize {
+        self.blocks
+    }
+
+    /// 合并另一个预留, 例如将 reserve_inodes 与 reserve_blocks 的结果合并为一个凭证
+    pub fn merge(&mut self, mut other: Reservation) {
+        self.inodes += other.inodes;
+        self.blocks += other.blocks;
+        other.inodes = 0;
+        other.blocks = 0;
+    }
+
+    /// 消耗一个预留的索引节点, 没有剩余时返回 false
+    pub(super) fn take_inode(&mut self) -> bool {
+        let taken = self.inodes > 0;
+        if taken {
+            self.inodes -= 1;
+        }
+        taken
+    }
+
+    /// 消耗一个预留的数据块, 没有剩余时返回 false
+    pub(super) fn take_block(&mut self) -> bool {
+        let taken = self.blocks > 0;
+        if taken {
+            self.blocks -= 1;
+        }
+        taken
+    }
+}
+
+impl Drop for Reservation {
+    /// 归还没有用完的预留. 注意不要在持有 fs 锁的时候 drop Reservation
+    fn drop(&mut self) {
+        if self.inodes > 0 || self.blocks > 0 {
+            self.fs.lock().release(self.inodes, self.blocks);
+        }
+    }
+}
@@ -0,0 +1,62 @@
+//! 文件系统运行时的统计信息
+//!
+//! [`Statistics`] 将块缓存, 块设备读写, 磁盘块/索引节点分配以及文件操作次数汇总在一起,
+//! 通过 [`FileSystem::statistics`](super::FileSystem::statistics) 获取自挂载 (create/open) 以来的计数
+
+/// 块缓存层的计数
+///
+/// 块设备的实际读写只会发生在块缓存层: 缓存未命中时 read_block, 同步脏块时 write_block.
+/// 因此块设备读块次数就是 misses, 不再单独计数
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CacheStats {
+    /// 缓存命中次数
+    pub hits: usize,
+    /// 缓存未命中次数
+    pub misses: usize,
+    /// 缓存替换次数
+    pub evictions: usize,
+    /// 块设备写块次数
+    pub block_writes: usize,
+}
+
+impl CacheStats {
+    /// 计算从 base 到当前的增量
+    pub fn since(&self, base: &CacheStats) -> CacheStats {
+        CacheStats {
+            hits: self.hits - base.hits,
+            misses: self.misses - base.misses,
+            evictions: self.evictions - base.evictions,
+            block_writes: self.block_writes - base.block_writes,
+        }
+    }
+}
+
+/// 索引节点/数据块的分配与回收次数
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AllocCounts {
+    pub inode_allocs: usize,
+    pub inode_deallocs: usize,
+    pub data_allocs: usize,
+    pub data_deallocs: usize,
+}
+
+/// 通过 Inode 暴露给使用者的文件操作次数
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OpCounts {
+    pub find: usize,
+    pub ls: usize,
+    pub create: usize,
+    pub read: usize,
+    pub write: usize,
+    pub clear: usize,
+    pub remove: usize,
+    pub rename: usize,
+}
+
+/// 自挂载以来的统计信息快照
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Statistics {
+    pub cache: CacheStats,
+    pub alloc: AllocCounts,
+    pub ops: OpCounts,
+}
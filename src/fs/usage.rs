@@ -0,0 +1,108 @@
+//! 磁盘空间占用统计
+//!
+//! 将整个镜像的块按用途划分: 超级块, 位图, 索引节点区域, 索引块, 目录项, 文件数据以及空闲块,
+//! 以便了解元数据开销与文件内容各占多少空间
+
+use std::sync::Arc;
+
+use spin::Mutex;
+
+use super::{get_block_cache, FileSystem, Inode, SuperBlock};
+
+/// 镜像的空间占用 (单位: 块)
+#[derive(Debug, Default)]
+pub struct Usage {
+    pub total_blocks: usize,
+    pub super_blocks: usize,
+    pub inode_bitmap_blocks: usize,
+    pub inode_area_blocks: usize,
+    pub data_bitmap_blocks: usize,
+    /// 文件/目录的一级, 二级索引块
+    pub index_blocks: usize,
+    /// 目录内容 (目录项) 占用的数据块
+    pub dir_blocks: usize,
+    /// 文件内容占用的数据块
+    pub file_blocks: usize,
+    /// Merkle 模式下节点表与叶子表占用的数据块
+    pub merkle_blocks: usize,
+    /// 数据区域中未分配的块
+    pub free_blocks: usize,
+    /// 数据位图中已分配, 但没有被任何文件/目录引用的块
+    pub leaked_blocks: usize,
+    pub inodes_used: usize,
+    pub inodes_total: usize,
+    /// 根目录下每一项 (递归统计) 占用的数据块和索引块
+    pub top_level: Vec<(String, usize)>,
+}
+
+impl Usage {
+    /// 统计文件系统的空间占用, 需要遍历整棵目录树
+    pub fn collect(fs: &Arc<Mutex<FileSystem>>) -> Self {
+        let mut usage = {
+            let fs = fs.lock();
+            let (
+                total_blocks,
+                inode_bitmap_blocks,
+                inode_area_blocks,
+                data_bitmap_blocks,
+                data_area_blocks,
+            ) = get_block_cache(0, Arc::clone(&fs.block_device))
+                .lock()
+                .read(0, |super_block: &SuperBlock| {
+                    (
+                        super_block.total_blocks as usize,
+                        super_block.inode_bitmap_blocks as usize,
+                        super_block.inode_area_blocks as usize,
+                        super_block.data_bitmap_blocks as usize,
+                        super_block.data_area_blocks as usize,
+                    )
+                });
+            let data_allocated = fs.data_bitmap.count_allocated(&fs.block_device);
+            Self {
+                total_blocks,
+                super_blocks: 1,
+                inode_bitmap_blocks,
+                inode_area_blocks,
+                data_bitmap_blocks,
+                merkle_blocks: fs.merkle_table_blocks(),
+                free_blocks: data_area_blocks - data_allocated,
+                // 先记为已分配的块数, 遍历目录树后再减去被引用的块
+                leaked_blocks: data_allocated,
+                inodes_used: fs.inode_bitmap.count_allocated(&fs.block_device),
+                inodes_total: fs.inode_bitmap.maximum(),
+                ..Default::default()
+            }
+        };
+
+        let root_inode = FileSystem::root_inode(fs);
+        let (dir_blocks, index_blocks) = root_inode.block_usage();
+        usage.dir_blocks += dir_blocks;
+        usage.index_blocks += index_blocks;
+        for name in root_inode.ls() {
+            let inode = root_inode.find(name.as_str()).unwrap();
+            let blocks = usage.walk(&inode);
+            usage.top_level.push((name, blocks));
+        }
+        usage.leaked_blocks = usage.leaked_blocks.saturating_sub(
+            usage.dir_blocks + usage.file_blocks + usage.index_blocks + usage.merkle_blocks,
+        );
+        usage
+    }
+
+    /// 递归统计 inode 及其子树占用的块, 返回该子树的总块数
+    fn walk(&mut self, inode: &Inode) -> usize {
+        let (data_blocks, index_blocks) = inode.block_usage();
+        self.index_blocks += index_blocks;
+        let mut blocks = data_blocks + index_blocks;
+        if inode.is_dir() {
+            self.dir_blocks += data_blocks;
+            for name in inode.ls() {
+                let child = inode.find(name.as_str()).unwrap();
+                blocks += self.walk(&child);
+            }
+        } else {
+            self.file_blocks += data_blocks;
+        }
+        blocks
+    }
+}
@@ -11,7 +11,8 @@ use crate::fs::{DirEntry, DIRENT_SIZE};
 use ::log::error;
 
 use super::{
-    block_cache_sync_all, fs::FileSystem, get_block_cache, BlockDevice, DiskInode, DiskInodeType,
+    block_cache_sync_all, fs::FileSystem, get_block_cache, merkle, timestamp_now, BlockDevice,
+    DiskInode, DiskInodeType, Reservation,
 };
 
 use spin::{Mutex, MutexGuard};
@@ -92,7 +93,8 @@ impl Inode {
     }
 
     pub fn find(&self, name: &str) -> Option<Arc<Inode>> {
-        let fs = self.fs.lock();
+        let mut fs = self.fs.lock();
+        fs.op_counts.find += 1;
         self.read_disk_inode(|disk_inode| {
             // 通过偏移 获取一个 disk_inode; 通过 get_ref(offset) 获取
             // 它首先调用 find_inode_id 方法
@@ -118,6 +120,90 @@ impl Inode {
         self.read_disk_inode(|disk_inode| disk_inode.size as usize)
     }
 
+    /// 获取 (atime, mtime)
+    pub fn times(&self) -> (u32, u32) {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| (disk_inode.atime, disk_inode.mtime))
+    }
+
+    /// 设置 atime/mtime 为任意时间, 而不仅仅是创建/写入时的当前时间
+    ///
+    /// 导入文件时可以借此保留 host 上的时间戳
+    pub fn set_times(&self, atime: u32, mtime: u32) {
+        let _fs = self.fs.lock();
+        self.modify_disk_inode(|disk_inode| {
+            disk_inode.atime = atime;
+            disk_inode.mtime = mtime;
+        });
+        block_cache_sync_all();
+    }
+
+    /// 将大小扩充到 new_size 需要额外分配的数据块 (包括索引块) 数量
+    ///
+    /// 用于在写入之前预留空间
+    pub fn blocks_needed(&self, new_size: usize) -> usize {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| {
+            let new_size = (new_size as u32).max(disk_inode.alloc_size);
+            disk_inode.blocks_num_needed(new_size) as usize
+        })
+    }
+
+    /// 获取 (数据块数, 索引块数), 按已分配的大小 alloc_size 计算
+    pub fn block_usage(&self) -> (usize, usize) {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| {
+            let data_blocks = disk_inode.data_blocks() as usize;
+            let total_blocks = DiskInode::total_blocks(disk_inode.alloc_size) as usize;
+            (data_blocks, total_blocks - data_blocks)
+        })
+    }
+
+    /// 预取目录的目录项所在的数据块以及各个子节点的 inode 所在的块, 最多 limit 个, 返回预取的块数
+    ///
+    /// 供 shell 在 cd 之后在后台线程中调用, 随后的 ls/stat 等操作就能直接命中块缓存.
+    /// 读取目录项时持有 fs 锁, 之后只通过 get_block_cache 将子节点的 inode 块读入缓存
+    pub fn prefetch(&self, limit: usize) -> usize {
+        let blocks = {
+            let fs = self.fs.lock();
+            self.read_disk_inode(|disk_inode| {
+                let mut blocks: Vec<usize> = Vec::new();
+                if !disk_inode.is_dir() {
+                    return blocks;
+                }
+                // 目录项所在的数据块在读取目录项时就已经读入缓存
+                for inner_id in 0..disk_inode.data_blocks() {
+                    if blocks.len() >= limit {
+                        return blocks;
+                    }
+                    blocks.push(disk_inode.get_block_id(inner_id, &self.block_device) as usize);
+                }
+                let file_count = (disk_inode.size as usize) / DIRENT_SIZE;
+                let mut dir_entry = DirEntry::create_empty();
+                for i in 0..file_count {
+                    if blocks.len() >= limit {
+                        break;
+                    }
+                    disk_inode.read_at(
+                        DIRENT_SIZE * i,
+                        dir_entry.as_bytes_mut(),
+                        &self.block_device,
+                    );
+                    // 同一个块中有 4 个 inode, 相邻的子节点往往共用一个块
+                    let (block_id, _) = fs.get_disk_inode_pos(dir_entry.inode_id());
+                    if !blocks.contains(&(block_id as usize)) {
+                        blocks.push(block_id as usize);
+                    }
+                }
+                blocks
+            })
+        };
+        for &block_id in blocks.iter() {
+            get_block_cache(block_id, Arc::clone(&self.block_device));
+        }
+        blocks.len()
+    }
+
     pub fn inode_info(&self) -> (usize, usize) {
         let _fs = self.fs.lock();
         (self.block_id, self.block_offset)
@@ -132,7 +218,8 @@ impl Inode {
     // 文件列举
     // ls 方法可以收集目录下的所有文件的文件名并以向量的形式返回,
     pub fn ls(&self) -> Vec<String> {
-        let _fs = self.fs.lock();
+        let mut fs = self.fs.lock();
+        fs.op_counts.ls += 1;
         self.read_disk_inode(|disk_inode| {
             let file_count = (disk_inode.size as usize) / DIRENT_SIZE;
             let mut v: Vec<String> = Vec::new();
@@ -156,7 +243,27 @@ impl Inode {
     // create 方法可以在目录下创建一个文件
     // 返回 文件的 Inode
     pub fn create(&self, name: &str, kind: DiskInodeType) -> Option<Arc<Inode>> {
+        self.create_inner(name, kind, None)
+    }
+
+    /// 使用预留的索引节点和数据块创建文件, 预留用完后再从未预留的部分分配
+    pub fn create_reserved(
+        &self,
+        name: &str,
+        kind: DiskInodeType,
+        reservation: &mut Reservation,
+    ) -> Option<Arc<Inode>> {
+        self.create_inner(name, kind, Some(reservation))
+    }
+
+    fn create_inner(
+        &self,
+        name: &str,
+        kind: DiskInodeType,
+        mut reservation: Option<&mut Reservation>,
+    ) -> Option<Arc<Inode>> {
         let mut fs = self.fs.lock();
+        fs.op_counts.create += 1;
         if self
             .modify_disk_inode(|disk_inode| {
                 assert!(disk_inode.is_dir());
@@ -170,7 +277,17 @@ impl Inode {
         }
 
         // 为新文件分配一个 inode 编号
-        let new_inode_id = fs.alloc_inode();
+        let new_inode_id = match reservation.as_deref_mut() {
+            Some(reservation) => fs.alloc_reserved_inode(reservation),
+            None => fs.alloc_inode(),
+        };
+        let new_inode_id = match new_inode_id {
+            Some(inode_id) => inode_id,
+            None => {
+                println!("no free inode left for {}", name);
+                return None;
+            }
+        };
         let (new_inode_block_id, new_inode_block_offset) = fs.get_disk_inode_pos(new_inode_id);
 
         get_block_cache(new_inode_block_id as usize, Arc::clone(&self.block_device))
@@ -184,12 +301,18 @@ impl Inode {
             });
 
         // 将待创建文件的目录项插入到目录的内容中, 使得之后可以索引到
-        self.modify_disk_inode(|disk_inode| {
+        let dir_id = fs.inode_id(self.block_id, self.block_offset);
+        let (inserted, merkle_old) = self.modify_disk_inode(|disk_inode| {
+            let check = fs
+                .merkle_tables()
+                .map(|tables| tables.check(dir_id, disk_inode));
             // 在目录中添加一个目录项
             let file_count = (disk_inode.size as usize) / DIRENT_SIZE;
             let new_size = (file_count + 1) * DIRENT_SIZE;
-            // 增加目录的大小
-            self.increase_size(new_size as u32, disk_inode, &mut fs);
+            // 增加目录的大小, 没有空闲的数据块时放弃
+            if !self.increase_size(new_size as u32, disk_inode, &mut fs, reservation) {
+                return (false, None);
+            }
             // 在目录的最后添加一个目录项
             let dir_entry = DirEntry::new(name, new_inode_id as u32);
             disk_inode.write_at(
@@ -198,7 +321,28 @@ impl Inode {
                 dir_entry.as_bytes(),
                 &self.block_device,
             );
+            // 目录项增加, 更新目录的 mtime
+            disk_inode.mtime = timestamp_now();
+
+            match (check, fs.merkle_tables()) {
+                (Some((old, ok)), Some(tables)) => {
+                    let is_dir = kind == DiskInodeType::Directory;
+                    tables.set_node(new_inode_id, merkle::empty_hash(is_dir), dir_id);
+                    tables.update(dir_id, disk_inode, ok);
+                    (true, Some(old))
+                }
+                _ => (true, None),
+            }
         });
+        if let Some(old) = merkle_old {
+            fs.merkle_propagate(dir_id, old);
+        }
+        if !inserted {
+            // 目录放不下新的目录项, 归还刚刚分配的 inode
+            fs.dealloc_inode(new_inode_id);
+            println!("no free data block left for {}", name);
+            return None;
+        }
 
         // Q: 这与上面的 new_inode_block_id, new_inode_block_offset 有什么区别?
         // let (block_id, block_offset) = fs.get_disk_inode_pos(new_inode_id);
@@ -213,34 +357,73 @@ impl Inode {
         )))
     }
 
+    /// 扩大到 new_size, 需要的数据块 (包括索引块) 必须全部分配成功,
+    /// 否则归还已经分配的数据块, 保持 disk_inode 不变并返回 false
     fn increase_size(
         &self,
         new_size: u32,
         disk_inode: &mut DiskInode,
         fs: &mut MutexGuard<FileSystem>,
-    ) {
+        mut reservation: Option<&mut Reservation>,
+    ) -> bool {
         if new_size < disk_inode.alloc_size {
             // fix: bug
             // 某种操作后(可能为 删除文件夹下一个有数据的文件)无法创建文件
             disk_inode.size = new_size;
-            return;
+            return true;
         }
 
         let blocks_needed = disk_inode.blocks_num_needed(new_size);
         let mut v: Vec<u32> = Vec::new();
         for _ in 0..blocks_needed {
-            v.push(fs.alloc_data());
+            let block_id = match reservation.as_deref_mut() {
+                Some(reservation) => fs.alloc_reserved_data(reservation),
+                None => fs.alloc_data(),
+            };
+            match block_id {
+                Some(block_id) => v.push(block_id),
+                None => {
+                    for block_id in v {
+                        fs.dealloc_data(block_id);
+                    }
+                    return false;
+                }
+            }
         }
         disk_inode.increase_size(new_size, v, &self.block_device);
+        true
     }
 
     // 文件删除
+    /// 用 content 替换当前目录下文件 name 的全部内容, 文件不存在时创建
+    ///
+    /// 同名的目录项是目录时拒绝写入: 对目录 clear 会回收它的目录项所在的块, 其中的文件就再也找不到了
+    pub fn replace_file(&self, name: &str, content: &[u8]) -> Result<(), String> {
+        let file = match self.find(name) {
+            Some(file) if file.is_dir() => return Err(format!("{} is a directory", name)),
+            Some(file) => file,
+            None => self
+                .create(name, DiskInodeType::File)
+                .ok_or_else(|| format!("cannot create {}", name))?,
+        };
+        file.clear();
+        if file.write(0, content) != content.len() {
+            return Err(format!("no space left for {}", name));
+        }
+        Ok(())
+    }
+
     // 在以某些标志位打开文件(例如带有 CREATE 标志打开一个已经存在的文件)的时候, 需要首先将文件清空.
     // 在索引到文件的 Inode 之后, 可以调用 clear 方法
     // 将该文件占据的索引块和数据块回收
     pub fn clear(&self) {
         let mut fs = self.fs.lock();
-        self.modify_disk_inode(|disk_inode| {
+        fs.op_counts.clear += 1;
+        let inode_id = fs.inode_id(self.block_id, self.block_offset);
+        let merkle_old = self.modify_disk_inode(|disk_inode| {
+            let check = fs
+                .merkle_tables()
+                .map(|tables| tables.check(inode_id, disk_inode));
             let size = disk_inode.alloc_size;
             let data_blocks_dealloc = disk_inode.clear_size(&self.block_device);
 
@@ -249,7 +432,14 @@ impl Inode {
             for data_block in data_blocks_dealloc.into_iter() {
                 fs.dealloc_data(data_block);
             }
+
+            let (old, ok) = check?;
+            fs.merkle_tables()?.update(inode_id, disk_inode, ok);
+            Some(old)
         });
+        if let Some(old) = merkle_old {
+            fs.merkle_propagate(inode_id, old);
+        }
 
         block_cache_sync_all();
     }
@@ -259,7 +449,8 @@ impl Inode {
     // 类似删除顺序表的某个元素
     // 这个方法感觉不是很好 时间复杂度O(n) 空间复杂度O(n)
     pub fn rm_dir_entry(&self, file_name: &str, parent_inode: Arc<Inode>) {
-        let _fs = self.fs.lock();
+        let mut fs = self.fs.lock();
+        fs.op_counts.remove += 1;
 
         // 找到dir_entry_pos
         let pos = parent_inode.dir_entry_pos(file_name); // 提前找到位置, 防止拿不到锁
@@ -268,7 +459,11 @@ impl Inode {
             return;
         }
         let pos = pos.unwrap();
-        parent_inode.modify_disk_inode(|disk_inode| {
+        let parent_id = fs.inode_id(parent_inode.block_id, parent_inode.block_offset);
+        let merkle_old = parent_inode.modify_disk_inode(|disk_inode| {
+            let check = fs
+                .merkle_tables()
+                .map(|tables| tables.check(parent_id, disk_inode));
             let file_count = (disk_inode.size as usize) / DIRENT_SIZE;
             let new_size = (file_count - 1) * DIRENT_SIZE;
 
@@ -308,7 +503,16 @@ impl Inode {
 
             // 修改size (ps: 可以去看看 layout::write 处提到的 bug-fix)
             disk_inode.size = new_size as u32;
+            // 目录项减少, 更新目录的 mtime
+            disk_inode.mtime = timestamp_now();
+
+            let (old, ok) = check?;
+            fs.merkle_tables()?.update(parent_id, disk_inode, ok);
+            Some(old)
         });
+        if let Some(old) = merkle_old {
+            fs.merkle_propagate(parent_id, old);
+        }
 
         block_cache_sync_all();
     }
@@ -339,14 +543,20 @@ impl Inode {
     // 注意: 和 DiskInode 一样, 这里的读写作用在字节序列的一段区间上
 
     pub fn read(&self, offset: usize, buf: &mut [u8]) -> usize {
-        let _fs = self.fs.lock();
+        let mut fs = self.fs.lock();
+        fs.op_counts.read += 1;
         self.read_disk_inode(|disk_inode| disk_inode.read_at(offset, buf, &self.block_device))
     }
 
     pub fn chname(&self, old_name: &str, new_name: &str) {
-        let _fs = self.fs.lock();
+        let mut fs = self.fs.lock();
+        fs.op_counts.rename += 1;
+        let inode_id = fs.inode_id(self.block_id, self.block_offset);
 
-        self.modify_disk_inode(|curr_inode| {
+        let merkle_old = self.modify_disk_inode(|curr_inode| {
+            let check = fs
+                .merkle_tables()
+                .map(|tables| tables.check(inode_id, curr_inode));
             // find file by name
             let file_count = (curr_inode.alloc_size as usize) / DIRENT_SIZE;
             let mut dir_entry = DirEntry::create_empty();
@@ -362,10 +572,19 @@ impl Inode {
                 if dir_entry.name() == old_name {
                     dir_entry.chname(new_name);
                     curr_inode.write_at(i * DIRENT_SIZE, dir_entry.as_bytes(), &self.block_device);
-                    break;
+                    // 目录项改名, 更新目录的 mtime
+                    curr_inode.mtime = timestamp_now();
+
+                    let (old, ok) = check?;
+                    fs.merkle_tables()?.update(inode_id, curr_inode, ok);
+                    return Some(old);
                 }
             }
+            None
         });
+        if let Some(old) = merkle_old {
+            fs.merkle_propagate(inode_id, old);
+        }
         // fix: 此时退出文件 cache 未同步, 再次打开时不会被修改(事实上可以在 main.rs 的 exit 中同步))
         block_cache_sync_all();
     }
@@ -376,6 +595,8 @@ impl Inode {
             println!("🐳 alloc_size: {} B.", disk_inode.alloc_size);
             println!("🐳 size: {} B.", disk_inode.size);
             println!("🐳 type: {:?}.", disk_inode.type_);
+            println!("🐳 atime: {}.", disk_inode.atime);
+            println!("🐳 mtime: {}.", disk_inode.mtime);
             println!("🐳 direct blocks: {:?}.", disk_inode.direct);
             println!("🐳 indirect1 block: {}.", disk_inode.indirect1);
             println!("🐳 indirect2 block: {}.", disk_inode.indirect2);
@@ -383,23 +604,66 @@ impl Inode {
     }
 
     pub fn write(&self, offset: usize, buf: &[u8]) -> usize {
+        self.write_inner(offset, buf, None)
+    }
+
+    /// 使用预留的数据块写入, 预留用完后再从未预留的部分分配
+    pub fn write_reserved(
+        &self,
+        offset: usize,
+        buf: &[u8],
+        reservation: &mut Reservation,
+    ) -> usize {
+        self.write_inner(offset, buf, Some(reservation))
+    }
+
+    fn write_inner(
+        &self,
+        offset: usize,
+        buf: &[u8],
+        reservation: Option<&mut Reservation>,
+    ) -> usize {
         let mut fs = self.fs.lock();
-        let size = self.modify_disk_inode(|disk_inode| -> usize {
+        fs.op_counts.write += 1;
+        let inode_id = fs.inode_id(self.block_id, self.block_offset);
+        let (size, merkle_old) = self.modify_disk_inode(|disk_inode| {
             if !disk_inode.is_file() {
                 error!("write to a non-file inode");
-                return 0;
+                return (0, None);
             }
+            // Merkle 模式下, 写入之前检查文件原来的内容与哈希表一致
+            let check = fs
+                .merkle_tables()
+                .map(|tables| tables.check_write(inode_id, disk_inode, offset, buf.len()));
 
             // 如果写入的数据超过了文件的大小, 则需要增加文件的大小
-            self.increase_size((offset + buf.len()) as u32, disk_inode, &mut fs);
+            if !self.increase_size(
+                (offset + buf.len()) as u32,
+                disk_inode,
+                &mut fs,
+                reservation,
+            ) {
+                error!("no free data block left, nothing written");
+                return (0, None);
+            }
             // 写入数据
             let write_size = disk_inode.write_at(offset, buf, &self.block_device);
 
             // 修改size (ps: 可以去看看 layout::write 处提到的bug-fix)
             disk_inode.size = (offset + write_size) as u32;
+            disk_inode.mtime = timestamp_now();
 
-            write_size
+            match (check, fs.merkle_tables()) {
+                (Some((old, ok)), Some(tables)) => {
+                    tables.update_write(inode_id, disk_inode, offset, write_size, ok);
+                    (write_size, Some(old))
+                }
+                _ => (write_size, None),
+            }
         });
+        if let Some(old) = merkle_old {
+            fs.merkle_propagate(inode_id, old);
+        }
         block_cache_sync_all();
         size
     }
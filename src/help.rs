@@ -0,0 +1,253 @@
+//! shell 命令表: 每个命令的用法, 说明以及接受的参数个数
+//!
+//! help 与 help <command> 根据命令表输出帮助,
+//! 主循环在执行命令之前根据参数个数检查输入, 参数不对时输出该命令的用法而不是 panic
+
+/// 一个 shell 命令的说明
+pub struct CommandSpec {
+    pub name: &'static str,
+    /// 一行简介
+    pub summary: &'static str,
+    /// 用法, 括号中的参数可以省略
+    pub usage: &'static str,
+    /// 参数与注意事项的补充说明
+    pub details: &'static [&'static str],
+    /// 最少的参数个数
+    pub min_args: usize,
+    /// 最多的参数个数, None 表示不限
+    pub max_args: Option<usize>,
+}
+
+impl CommandSpec {
+    /// 参数个数是否符合要求
+    pub fn accepts(&self, args: usize) -> bool {
+        args >= self.min_args && self.max_args.is_none_or(|max| args <= max)
+    }
+
+    /// 输出完整的帮助
+    pub fn print_help(&self) {
+        println!("🐳 {}: {}", self.name, self.summary);
+        println!("   🍡 usage: {}", self.usage);
+        for detail in self.details {
+            println!("   🍡 {}", detail);
+        }
+        println!();
+    }
+
+    /// 输出错误信息以及该命令的用法
+    pub fn usage_error(&self, message: &str) {
+        println!("🦀 {}: {}! 🦐", self.name, message);
+        println!("   🍡 usage: {}", self.usage);
+    }
+}
+
+/// 根据命令名查找命令表
+pub fn find(name: &str) -> Option<&'static CommandSpec> {
+    COMMANDS.iter().find(|spec| spec.name == name)
+}
+
+pub const COMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "help",
+        summary: "show helps.",
+        usage: "help (command)",
+        details: &["command: show the usage and options of one command."],
+        min_args: 0,
+        max_args: Some(1),
+    },
+    CommandSpec {
+        name: "ls",
+        summary: "list all files in current folder.",
+        usage: "ls",
+        details: &[],
+        min_args: 0,
+        max_args: Some(0),
+    },
+    CommandSpec {
+        name: "cd",
+        summary: "change current folder.",
+        usage: "cd (folder | .. | /)",
+        details: &[],
+        min_args: 0,
+        max_args: Some(1),
+    },
+    CommandSpec {
+        name: "cat",
+        summary: "print file content.",
+        usage: "cat (--host) file_name",
+        details: &[
+            "--host: write the raw bytes to host stdout without any banner, e.g. for | sha256sum.",
+        ],
+        min_args: 1,
+        max_args: Some(2),
+    },
+    CommandSpec {
+        name: "touch",
+        summary: "create a file, or update its access/modify time.",
+        usage: "touch (-d time | -r ref_file) file_name",
+        details: &[
+            "-d: use time instead of now, e.g. 2023-04-01T08:00:00, 2023-04-01 or @1680307200.",
+            "-r: use the times of ref_file instead of now.",
+        ],
+        min_args: 1,
+        max_args: Some(5),
+    },
+    CommandSpec {
+        name: "mkdir",
+        summary: "create a folder.",
+        usage: "mkdir folder_name",
+        details: &[],
+        min_args: 1,
+        max_args: Some(1),
+    },
+    CommandSpec {
+        name: "stat",
+        summary: "show file or folder stat.",
+        usage: "stat file_name",
+        details: &[],
+        min_args: 1,
+        max_args: Some(1),
+    },
+    CommandSpec {
+        name: "get",
+        summary: "a test of fs, getting files to host form root directory.",
+        usage: "get (file_name -)",
+        details: &["-: write the raw bytes of file_name to host stdout instead."],
+        min_args: 0,
+        max_args: Some(2),
+    },
+    CommandSpec {
+        name: "set",
+        summary: "a test of fs, setting host files (src files of fs) to root directory.",
+        usage: "set (--symlinks=follow|skip) (--oversize=error|truncate|skip) (--manifest)",
+        details: &[
+            "directories, FIFOs, sockets and devices are skipped with a warning.",
+            "--oversize: the max file size is also limited by the free space of the image.",
+            "--manifest: record how the image was packed in /.manifest (see inspect).",
+        ],
+        min_args: 0,
+        max_args: Some(3),
+    },
+    CommandSpec {
+        name: "inspect",
+        summary: "show the manifest recorded by set --manifest.",
+        usage: "inspect --provenance",
+        details: &[],
+        min_args: 1,
+        max_args: Some(1),
+    },
+    CommandSpec {
+        name: "fmt",
+        summary: "format easy-fs.",
+        usage: "fmt",
+        details: &[],
+        min_args: 0,
+        max_args: Some(0),
+    },
+    CommandSpec {
+        name: "stats",
+        summary: "show cache, I/O, alloc and op counts since mount.",
+        usage: "stats",
+        details: &[],
+        min_args: 0,
+        max_args: Some(0),
+    },
+    CommandSpec {
+        name: "alias",
+        summary: "list aliases, or define one (saved in /.profile).",
+        usage: "alias (name='command args')",
+        details: &["e.g. alias la='ls'"],
+        min_args: 0,
+        max_args: None,
+    },
+    CommandSpec {
+        name: "unalias",
+        summary: "remove an alias.",
+        usage: "unalias name",
+        details: &[],
+        min_args: 1,
+        max_args: Some(1),
+    },
+    CommandSpec {
+        name: "usage",
+        summary: "show how the image is used (metadata, file data, free, per top-level entry).",
+        usage: "usage",
+        details: &[],
+        min_args: 0,
+        max_args: Some(0),
+    },
+    CommandSpec {
+        name: "merkle",
+        summary: "turn merkle mode on/off (root hash of the tree kept in super block).",
+        usage: "merkle on/off",
+        details: &[
+            "on: trust the current tree, hashes of every block and inode are kept in hidden tables.",
+            "    the hashes are updated by write, create, rm and chname, not by re-reading the tree.",
+            "off: drop the hash tables.",
+        ],
+        min_args: 1,
+        max_args: Some(1),
+    },
+    CommandSpec {
+        name: "verify",
+        summary: "check the tree against the root hash in super block.",
+        usage: "verify --merkle",
+        details: &[
+            "lists files modified outside easy-fs, the trusted root hash is never updated.",
+            "run merkle on again to trust the current tree.",
+        ],
+        min_args: 1,
+        max_args: Some(1),
+    },
+    CommandSpec {
+        name: "exit",
+        summary: "exit easy-fs.",
+        usage: "exit",
+        details: &[],
+        min_args: 0,
+        max_args: Some(0),
+    },
+    CommandSpec {
+        name: "chname",
+        summary: "change file or folder name.",
+        usage: "chname old_name new_name",
+        details: &[
+            "note: the length of new_name is expected to be less than 27 ascii characters,",
+            "      or no more than 9 unicode characters.",
+        ],
+        min_args: 2,
+        max_args: Some(2),
+    },
+    CommandSpec {
+        name: "rm",
+        summary: "remove files or folders.",
+        usage: "rm file1 folder2 file3 ...",
+        details: &[],
+        min_args: 1,
+        max_args: None,
+    },
+    CommandSpec {
+        name: "write",
+        summary: "write content to file.",
+        usage: "write file_name (offset or \"-a\")",
+        details: &[
+            "offset: write content to file from offset.",
+            "-a: append content to file.",
+            "note: contents end with newline EOF, or at the end of input.",
+        ],
+        min_args: 1,
+        max_args: Some(2),
+    },
+    CommandSpec {
+        name: "read",
+        summary: "read content from file.",
+        usage: "read file_name (offset) (length)",
+        details: &[
+            "offset: read content from file from offset.",
+            "length: read content length.",
+            "if offset and length are not set, read all content.",
+        ],
+        min_args: 1,
+        max_args: Some(3),
+    },
+];
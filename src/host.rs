@@ -0,0 +1,315 @@
+//! host 文件系统与 easy-fs 之间导入/导出 (set/get) 时用到的辅助函数
+//!
+//! 稀疏文件 (sparse file) 中的空洞 (hole) 在 host 上并不占用磁盘空间, 读出来却全是 0.
+//! 如果导入/导出时按普通文件整体读写, 像磁盘镜像这样的大文件在一次往返之后就会膨胀成实际大小.
+//! 因此导入时借助 SEEK_DATA/SEEK_HOLE 只搬运数据段, 导出时跳过全 0 的块, 由 host 重新生成空洞.
+//!
+//! 导入前先通过 [`plan_import`] 按 [`ImportPolicy`] 筛选 host 目录中的条目:
+//! 符号链接, FIFO/socket 等特殊文件, 子目录, 超过最大文件大小的文件都在写入之前处理掉,
+//! 而不是导入到一半时 panic.
+//!
+//! 最大文件大小由 [`max_import_len`] 给出: 既不能超过 MAX_FILE_SIZE (二级索引的范围),
+//! 也不能超过镜像中剩余的空间. 默认大小的镜像的数据区域比 MAX_FILE_SIZE 还小,
+//! 如果只按 MAX_FILE_SIZE 截断, --oversize=truncate 之后的预留总是会失败.
+
+use std::{
+    fs::{metadata, read_dir, symlink_metadata, File},
+    io::{Error, ErrorKind, Read, Seek, SeekFrom, Write},
+    sync::Arc,
+};
+
+use spin::Mutex;
+
+use crate::fs::{
+    DiskInode, FileSystem, Inode, Reservation, BLOCK_SIZE, DIRENT_SIZE, NAME_LENGTH_LIMIT,
+};
+
+/// 如何处理 host 中的符号链接
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// 导入链接指向的文件 (默认)
+    Follow,
+    /// 跳过并给出警告
+    Skip,
+}
+
+/// 如何处理超过最大文件大小 ([`max_import_len`]) 的文件
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OversizePolicy {
+    /// 放弃整个导入 (默认)
+    Error,
+    /// 只导入前 max_len 字节
+    Truncate,
+    /// 跳过并给出警告
+    Skip,
+}
+
+/// 导入策略, FIFO/socket/设备文件与子目录总是跳过
+#[derive(Clone, Copy, Debug)]
+pub struct ImportPolicy {
+    pub symlinks: SymlinkPolicy,
+    pub oversize: OversizePolicy,
+}
+
+impl Default for ImportPolicy {
+    fn default() -> Self {
+        Self {
+            symlinks: SymlinkPolicy::Follow,
+            oversize: OversizePolicy::Error,
+        }
+    }
+}
+
+impl ImportPolicy {
+    /// 解析 set 的参数: --symlinks=follow|preserve|skip, --oversize=error|truncate|skip
+    pub fn parse<'a>(args: impl Iterator<Item = &'a str>) -> Result<Self, String> {
+        let mut policy = Self::default();
+        for arg in args {
+            match arg.split_once('=') {
+                Some(("--symlinks", value)) => {
+                    policy.symlinks = match value {
+                        "follow" => SymlinkPolicy::Follow,
+                        "skip" => SymlinkPolicy::Skip,
+                        "preserve" => {
+                            return Err(
+                                "easy-fs has no symlinks yet, use --symlinks=follow or skip"
+                                    .to_string(),
+                            )
+                        }
+                        _ => return Err(format!("unknown symlink policy: {}", value)),
+                    }
+                }
+                Some(("--oversize", value)) => {
+                    policy.oversize = match value {
+                        "error" => OversizePolicy::Error,
+                        "truncate" => OversizePolicy::Truncate,
+                        "skip" => OversizePolicy::Skip,
+                        _ => return Err(format!("unknown oversize policy: {}", value)),
+                    }
+                }
+                _ => return Err(format!("unknown option: {}", arg)),
+            }
+        }
+        Ok(policy)
+    }
+}
+
+/// 待导入的 host 文件
+pub struct ImportEntry {
+    pub name: String,
+    pub path: String,
+    /// 要导入的字节数 (--oversize=truncate 时可能小于 host 文件大小)
+    pub len: u64,
+}
+
+/// 在目录 dir 中导入一个文件时最多能导入的字节数: 不超过 MAX_FILE_SIZE,
+/// 也不超过镜像中剩余的数据块 (除去新目录项可能需要的块) 能容纳的大小.
+///
+/// 只考虑单个文件, 同时导入多个文件时仍然可能因为总的空间不足而预留失败
+pub fn max_import_len(fs: &Arc<Mutex<FileSystem>>, dir: &Inode) -> u64 {
+    let dir_blocks = dir.blocks_needed(dir.size() + DIRENT_SIZE);
+    let free = fs.lock().free_data_blocks();
+    DiskInode::max_size(free.saturating_sub(dir_blocks) as u32) as u64
+}
+
+/// 按照策略筛选 src_path 目录中要导入的文件, 被跳过的条目打印警告.
+/// 大于 max_len (见 [`max_import_len`]) 的文件按 policy.oversize 处理.
+///
+/// 遇到 --oversize=error 的超大文件时返回 Err, 此时还没有写入任何内容
+pub fn plan_import(
+    src_path: &str,
+    policy: &ImportPolicy,
+    max_len: u64,
+) -> Result<Vec<ImportEntry>, String> {
+    let dir = read_dir(src_path).map_err(|err| format!("{}: {}", src_path, err))?;
+    let mut entries = Vec::new();
+    for dir_entry in dir {
+        let dir_entry = dir_entry.map_err(|err| format!("{}: {}", src_path, err))?;
+        let path = format!("{}{}", src_path, dir_entry.file_name().to_string_lossy());
+        let name = match dir_entry.file_name().into_string() {
+            Ok(name) if name.len() <= NAME_LENGTH_LIMIT => name,
+            Ok(name) => {
+                println!(
+                    "🍡 skip {}: name longer than {} bytes",
+                    name, NAME_LENGTH_LIMIT
+                );
+                continue;
+            }
+            Err(_) => {
+                println!("🍡 skip {}: name is not valid UTF-8", path);
+                continue;
+            }
+        };
+        let mut meta = match symlink_metadata(&path) {
+            Ok(meta) => meta,
+            Err(err) => {
+                println!("🍡 skip {}: {}", name, err);
+                continue;
+            }
+        };
+        if meta.file_type().is_symlink() {
+            if policy.symlinks == SymlinkPolicy::Skip {
+                println!("🍡 skip {}: symlink", name);
+                continue;
+            }
+            meta = match metadata(&path) {
+                Ok(meta) => meta,
+                Err(err) => {
+                    println!("🍡 skip {}: broken symlink ({})", name, err);
+                    continue;
+                }
+            };
+        }
+        if meta.is_dir() {
+            println!("🍡 skip {}: directory", name);
+            continue;
+        }
+        if !meta.is_file() {
+            // FIFO, socket, 设备文件等: 读取可能阻塞或者没有意义
+            println!("🍡 skip {}: not a regular file", name);
+            continue;
+        }
+        let mut len = meta.len();
+        if len > max_len {
+            match policy.oversize {
+                OversizePolicy::Error => {
+                    return Err(format!(
+                        "{} is {} bytes, larger than the max file size {} bytes",
+                        name, len, max_len
+                    ))
+                }
+                OversizePolicy::Truncate => {
+                    println!("🍡 truncate {}: {} -> {} bytes", name, len, max_len);
+                    len = max_len;
+                }
+                OversizePolicy::Skip => {
+                    println!(
+                        "🍡 skip {}: {} bytes is larger than the max file size {} bytes",
+                        name, len, max_len
+                    );
+                    continue;
+                }
+            }
+        }
+        entries.push(ImportEntry { name, path, len });
+    }
+    Ok(entries)
+}
+
+/// 获取 host 文件中所有数据段 (非空洞部分) 的区间 [start, end)
+///
+/// 如果 host 的文件系统不支持 SEEK_DATA/SEEK_HOLE, 则把整个文件视为一个数据段
+#[cfg(unix)]
+pub fn data_segments(file: &File, len: u64) -> Vec<(u64, u64)> {
+    use std::os::unix::io::AsRawFd;
+
+    let fd = file.as_raw_fd();
+    let mut segments: Vec<(u64, u64)> = Vec::new();
+    let mut offset = 0u64;
+    while offset < len {
+        // 从 offset 开始找到下一个数据段的开头, 如果后面全是空洞则返回 -1 (ENXIO)
+        let start = unsafe { libc::lseek(fd, offset as libc::off_t, libc::SEEK_DATA) };
+        if start < 0 {
+            if offset == 0 && std::io::Error::last_os_error().raw_os_error() != Some(libc::ENXIO) {
+                // 不支持 SEEK_DATA
+                return vec![(0, len)];
+            }
+            break;
+        }
+        // 再找到这个数据段之后的第一个空洞, 文件末尾总被视为一个空洞
+        let end = unsafe { libc::lseek(fd, start, libc::SEEK_HOLE) };
+        if end < 0 {
+            return vec![(0, len)];
+        }
+        let (start, end) = (start as u64, (end as u64).min(len));
+        if start >= end {
+            break;
+        }
+        segments.push((start, end));
+        offset = end;
+    }
+    segments
+}
+
+#[cfg(not(unix))]
+pub fn data_segments(_file: &File, len: u64) -> Vec<(u64, u64)> {
+    vec![(0, len)]
+}
+
+/// 将 host 文件导入到 easy-fs 的文件 inode 中, 只读写 host 文件中的数据段
+///
+/// 空洞部分不会被写入: 数据块在分配之前总是全 0 的 (create 时清零, dealloc_data 时清零),
+/// 因此跳过空洞即可保证读出来的内容与 host 文件一致.
+///
+/// 只导入 host 文件的前 len 字节, 写入时优先消耗 reservation 中预留的数据块, 返回导入的文件大小.
+/// easy-fs 的空间不足以写完时返回 ErrorKind::WriteZero
+pub fn import_file(
+    host_file: &mut File,
+    inode: &Inode,
+    len: u64,
+    reservation: &mut Reservation,
+) -> std::io::Result<usize> {
+    let mut buf: Vec<u8> = Vec::new();
+    let mut last_end = 0u64;
+    for (start, end) in data_segments(host_file, len) {
+        buf.resize((end - start) as usize, 0);
+        host_file.seek(SeekFrom::Start(start))?;
+        host_file.read_exact(&mut buf)?;
+        let written = inode.write_reserved(start as usize, &buf, reservation);
+        check_written(start, written, buf.len())?;
+        last_end = end;
+    }
+    // 文件以空洞结尾时, 写入最后一个字节 (0) 使文件大小正确
+    if last_end < len {
+        let written = inode.write_reserved(len as usize - 1, &[0u8], reservation);
+        check_written(len - 1, written, 1)?;
+    }
+    Ok(len as usize)
+}
+
+/// easy-fs 没有空闲的数据块时 write 返回 0, 此时导入失败而不是当作导入成功
+fn check_written(offset: u64, written: usize, expected: usize) -> std::io::Result<()> {
+    if written == expected {
+        return Ok(());
+    }
+    Err(Error::new(
+        ErrorKind::WriteZero,
+        format!(
+            "only {} of {} bytes written at offset {}, no space left in easy-fs",
+            written, expected, offset
+        ),
+    ))
+}
+
+/// 将 easy-fs 中的文件内容原样 (不加任何提示信息) 按块写到 out 中, 例如 host 的 stdout,
+/// 以便 `cat --host kernel | sha256sum` 这样通过管道处理, 而不需要先导出到临时文件
+pub fn stream_file(inode: &Inode, out: &mut impl Write) -> std::io::Result<usize> {
+    let size = inode.size();
+    let mut buf = [0u8; BLOCK_SIZE];
+    let mut offset = 0usize;
+    while offset < size {
+        let len = inode.read(offset, &mut buf);
+        out.write_all(&buf[..len])?;
+        offset += len;
+    }
+    out.flush()?;
+    Ok(size)
+}
+
+/// 将 easy-fs 中的文件内容导出到 host 文件中, 全 0 的块不写入而是直接 seek 跳过,
+/// 最后通过 set_len 确定文件大小, 由 host 在跳过的部分生成空洞
+pub fn export_file(inode: &Inode, host_file: &mut File) -> std::io::Result<usize> {
+    let size = inode.size();
+    let mut buf = [0u8; BLOCK_SIZE];
+    let mut offset = 0usize;
+    while offset < size {
+        let len = inode.read(offset, &mut buf);
+        if buf[..len].iter().any(|&byte| byte != 0) {
+            host_file.seek(SeekFrom::Start(offset as u64))?;
+            host_file.write_all(&buf[..len])?;
+        }
+        offset += len;
+    }
+    host_file.set_len(size as u64)?;
+    Ok(size)
+}
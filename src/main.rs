@@ -1,6 +1,8 @@
 use crate::{
+    alias::Aliases,
     cell::UnSafeCell,
     fs::{block_cache_sync_all, Inode},
+    manifest::Manifest,
 };
 use chrono::{
     format::{DelayedFormat, StrftimeItems},
@@ -11,14 +13,20 @@ use device::BlockFile;
 use fs::{FileSystem, BLOCK_SIZE};
 use lazy_static::*;
 use std::{
-    fs::{read_dir, File, OpenOptions},
-    io::{stdin, stdout, Read, Write},
+    fs::{File, OpenOptions},
+    io::{stdin, stdout, IsTerminal, Write},
     sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
 };
 
+mod alias;
 mod cell;
 mod device;
 mod fs;
+mod help;
+mod host;
+mod manifest;
+mod preflight;
 mod test;
 
 pub const BLOCK_NUM: usize = 0x4000;
@@ -60,7 +68,17 @@ fn fs_pack() -> std::io::Result<()> {
                 .short('w')
                 .long("ways")
                 .required(true)
-                .help("Executable ways use \"create\" or \"open\""),
+                .help("Executable ways use \"create\", \"open\" or \"preflight\""),
+        )
+        .arg(
+            // allocator 参数
+            Arg::new("allocator")
+                .short('a')
+                .long("allocator")
+                .default_value("first-fit")
+                .help(
+                    "Data block allocation strategy: first-fit, next-fit, best-fit or group-aware",
+                ),
         )
         .get_matches();
 
@@ -80,6 +98,14 @@ fn fs_pack() -> std::io::Result<()> {
 
     let ways = matche.get_one("ways to run").map(String::as_str).unwrap();
 
+    // 只检查后端存储, 不创建镜像
+    if ways == "preflight" {
+        if !preflight::preflight(target_path, (BLOCK_NUM * BLOCK_SIZE) as u64) {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     // 创建虚拟块设备
     // 打开虚拟块设备.这里我们在 Linux 上创建文件 ./target/fs.img 来新建一个虚拟块设备, 并将它的容量设置为 0x4000 个块.
     // 在创建的时候需要将它的访问权限设置为可读可写.
@@ -104,28 +130,58 @@ fn fs_pack() -> std::io::Result<()> {
         let efs = FileSystem::open(block_file.clone());
         efs
     } else {
-        panic!("🦀 Please specify the operation(create or open)!");
+        panic!("🦀 Please specify the operation(create, open or preflight)!");
     };
 
+    // 挂载后设置数据块的分配策略
+    let allocator = matche.get_one("allocator").map(String::as_str).unwrap();
+    efs.lock().set_allocator(
+        fs::allocator_by_name(allocator)
+            .unwrap_or_else(|| panic!("🦀 Unknown allocator: {}!", allocator)),
+    );
+
     // 读取目录
     let root_inode = Arc::new(FileSystem::root_inode(&efs));
+    // 读入 /.profile 中保存的别名
+    let mut aliases = Aliases::load(&root_inode);
     let mut folder_inode: Vec<Arc<Inode>> = Vec::new();
     let mut curr_folder_inode = Arc::clone(&root_inode);
+    // cd 之后在后台预取目录的线程
+    let mut prefetch: Option<JoinHandle<usize>> = None;
 
     loop {
-        // shell display
-        print!("{}", PATH.borrow());
-        stdout().flush().expect("🦀 Failed to flush stdout :(");
+        // shell display (stdin 不是终端时不显示提示符, 以免混入管道的输出中)
+        if stdin().is_terminal() {
+            print!("{}", PATH.borrow());
+            stdout().flush().expect("🦀 Failed to flush stdout :(");
+        }
 
         // Take in user input
         let mut input = String::new();
-        stdin()
+        let read = stdin()
             .read_line(&mut input)
             .expect("🦀 Failed to read input :(");
+        if read == 0 {
+            // 输入结束 (EOF) 时与 exit 一样退出
+            input = String::from("exit");
+        }
+
+        // 在解析之前展开别名
+        let input = aliases.expand(&input);
 
         // Split input into command and args
         let mut input = input.trim().split_whitespace(); // Shadows String with SplitWhitespace Iterator
-        let cmd = input.next().unwrap();
+        let cmd = match input.next() {
+            Some(cmd) => cmd,
+            None => continue,
+        };
+        // 参数个数不对时输出该命令的用法
+        if let Some(spec) = help::find(cmd) {
+            if !spec.accepts(input.clone().count()) {
+                spec.usage_error("Wrong number of arguments");
+                continue;
+            }
+        }
         match cmd {
             "cd" => {
                 let mut copy_input = input.clone();
@@ -178,16 +234,68 @@ fn fs_pack() -> std::io::Result<()> {
                 }
 
                 update_path(input.next().unwrap_or(""));
+
+                // 在后台将新目录的目录项和子节点的 inode 块读入缓存, 随后的 ls/stat 直接命中缓存.
+                // 最多占用一半的块缓存, 以免把 shell 正在使用的块都替换出去
+                if let Some(handle) = prefetch.take() {
+                    let _ = handle.join();
+                }
+                let dir = Arc::clone(&curr_folder_inode);
+                prefetch = Some(thread::spawn(move || {
+                    dir.prefetch(fs::BLOCK_CACHE_SIZE / 2)
+                }));
             }
 
+            // touch (-d time | -r ref_file) file_name
+            // 文件不存在时创建文件; 文件已存在, 或指定了 -d/-r 时设置 atime/mtime
             "touch" => {
-                let file_name = input.next();
+                let mut file_name = None;
+                let mut times: Option<(u32, u32)> = None;
+                let mut invalid = false;
+                while let Some(arg) = input.next() {
+                    match arg {
+                        "-d" => match input.next().and_then(parse_time) {
+                            Some(time) => times = Some((time, time)),
+                            None => {
+                                println!("🦀 touch: Invalid date! (e.g. 2023-04-01T08:00:00, 2023-04-01 or @1680307200) 🦐");
+                                invalid = true;
+                            }
+                        },
+                        "-r" => match input.next().and_then(|name| curr_folder_inode.find(name)) {
+                            Some(inode) => times = Some(inode.times()),
+                            None => {
+                                println!("🦀 touch: Reference file not found! 🦐");
+                                invalid = true;
+                            }
+                        },
+                        _ => file_name = Some(arg),
+                    }
+                }
+                if invalid {
+                    continue;
+                }
                 if file_name.is_none() {
                     println!("🦀 touch: Miss file name! 🦐");
                     continue;
                 }
                 let file_name = file_name.unwrap();
-                curr_folder_inode.create(file_name, fs::DiskInodeType::File);
+                let inode = match curr_folder_inode.find(file_name) {
+                    Some(inode) => {
+                        // 已存在的文件默认更新为当前时间
+                        times.get_or_insert_with(|| {
+                            let now = fs::timestamp_now();
+                            (now, now)
+                        });
+                        inode
+                    }
+                    None => match curr_folder_inode.create(file_name, fs::DiskInodeType::File) {
+                        Some(inode) => inode,
+                        None => continue,
+                    },
+                };
+                if let Some((atime, mtime)) = times {
+                    inode.set_times(atime, mtime);
+                }
             }
 
             "mkdir" => {
@@ -228,9 +336,17 @@ fn fs_pack() -> std::io::Result<()> {
                 // 如果 input 只有两个参数, 那么就是读取文件的一部分: offset = 第一个参数, size = 文件大小 - offset
                 let next1 = input.next().unwrap_or("0");
                 let next2 = input.next();
+                let offset = match next1.parse::<usize>() {
+                    Ok(offset) => offset,
+                    Err(_) => {
+                        help::find("read")
+                            .unwrap()
+                            .usage_error("Offset must be a number");
+                        continue;
+                    }
+                };
                 if next2 == None {
                     // 读取整个文件
-                    let offset = next1.parse::<usize>().unwrap();
                     if size < offset {
                         println!("🦀 read: Offset is too large! 🦐");
                         continue;
@@ -243,8 +359,15 @@ fn fs_pack() -> std::io::Result<()> {
                     }
                 } else {
                     // 读取文件的一部分
-                    let offset = next1.parse::<usize>().unwrap();
-                    let size = next2.unwrap().parse::<usize>().unwrap();
+                    let size = match next2.unwrap().parse::<usize>() {
+                        Ok(size) => size,
+                        Err(_) => {
+                            help::find("read")
+                                .unwrap()
+                                .usage_error("Length must be a number");
+                            continue;
+                        }
+                    };
                     let mut buf = vec![0u8; size];
                     file_inode.read(offset, &mut buf);
                     unsafe {
@@ -257,6 +380,18 @@ fn fs_pack() -> std::io::Result<()> {
 
             "cat" => {
                 let file_name = input.next();
+                if file_name == Some("--host") {
+                    // 将文件原样输出到 host 的 stdout, 错误信息输出到 stderr
+                    match input.next().and_then(|name| curr_folder_inode.find(name)) {
+                        Some(inode) => {
+                            if let Err(err) = host::stream_file(&inode, &mut stdout().lock()) {
+                                eprintln!("🦀 cat: {} 🦐", err);
+                            }
+                        }
+                        None => eprintln!("🦀 cat: File not found! 🦐"),
+                    }
+                    continue;
+                }
                 if file_name.is_none() {
                     println!("🦀 cat: Miss file name! 🦐");
                     continue;
@@ -351,8 +486,13 @@ fn fs_pack() -> std::io::Result<()> {
                     // 如果是 "a" 则追加 append
                     if arg.parse::<usize>().is_err() && arg == "-a" {
                         offset = file_inode.size();
+                    } else if let Ok(arg) = arg.parse::<usize>() {
+                        offset = arg;
                     } else {
-                        offset = arg.parse::<usize>().unwrap();
+                        help::find("write")
+                            .unwrap()
+                            .usage_error("Offset must be a number or \"-a\"");
+                        continue;
                     }
                 } else {
                     offset = 0;
@@ -360,16 +500,22 @@ fn fs_pack() -> std::io::Result<()> {
 
                 println!("🐳 write: Please input content, end with newline EOF. 🐬");
 
+                // 最后写入的一行是否以换行结尾
+                let mut newline = false;
                 loop {
                     let mut content: String = String::new();
-                    stdin().read_line(&mut content).unwrap();
-                    if content == "EOF" || content == "EOF\n" {
+                    let read = stdin().read_line(&mut content).unwrap();
+                    // 读到 0 字节说明输入已经结束 (例如管道关闭或者 Ctrl-D), 与 EOF 行一样结束写入
+                    if read == 0 || content.trim_end_matches('\n') == "EOF" {
                         // 让文件的最后一行不是空行
-                        file_inode.write(offset - 1, "".as_bytes());
+                        if newline {
+                            file_inode.write(offset - 1, "".as_bytes());
+                        }
                         break;
                     }
                     file_inode.write(offset, content.as_bytes());
                     offset += content.len();
+                    newline = content.ends_with('\n');
                 }
             }
 
@@ -389,7 +535,18 @@ fn fs_pack() -> std::io::Result<()> {
                 let file_inode = file_inode.unwrap();
                 let size = file_inode.size();
                 let (block_id, block_offset) = file_inode.inode_info();
+                let (atime, mtime) = file_inode.times();
                 println!("🐳 The size of {} is {} B.", file_name, size);
+                println!(
+                    "🐳 The access time of {} is {}.",
+                    file_name,
+                    format_time(atime)
+                );
+                println!(
+                    "🐳 The modify time of {} is {}.",
+                    file_name,
+                    format_time(mtime)
+                );
                 println!("🐳 The block_id of {}'s inode is {}.", file_name, block_id);
                 println!(
                     "🐳 The block_offset of {}'s inode is {}.",
@@ -401,13 +558,27 @@ fn fs_pack() -> std::io::Result<()> {
 
             // 从 easy-fs 读取文件保存到 host 文件系统中
             "get" => {
+                // get file_name -: 将文件原样输出到 host 的 stdout
+                if let Some(file_name) = input.next() {
+                    if input.next() != Some("-") {
+                        println!("🦀 get: usage: get (file_name -) 🦐");
+                        continue;
+                    }
+                    match curr_folder_inode.find(file_name) {
+                        Some(inode) => {
+                            if let Err(err) = host::stream_file(&inode, &mut stdout().lock()) {
+                                eprintln!("🦀 get: {} 🦐", err);
+                            }
+                        }
+                        None => eprintln!("🦀 get: File not found! 🦐"),
+                    }
+                    continue;
+                }
                 for file in curr_folder_inode.ls() {
                     // 从easy-fs中读取文件
                     println!("🐬 Get {} from easy-fs.", file);
                     let inode = curr_folder_inode.find(file.as_str()).unwrap();
-                    let mut all_data: Vec<u8> = vec![0; inode.size() as usize];
-                    inode.read(0, &mut all_data);
-                    // 写入文件 保存到host文件系统中
+                    // 写入文件 保存到host文件系统中 (全 0 的块在 host 上重新生成空洞)
                     let mut target_file = File::create(format!(
                         "{}{} {}",
                         target_path,
@@ -421,35 +592,122 @@ fn fs_pack() -> std::io::Result<()> {
                         file
                     ))
                     .unwrap();
-                    target_file.write_all(all_data.as_slice()).unwrap();
+                    host::export_file(&inode, &mut target_file).unwrap();
                 }
             }
 
             // 读取 src_path 下的所有文件 保存到 easy-fs 中
             "set" => {
-                let files: Vec<_> = read_dir(src_path)
-                    .unwrap()
-                    .into_iter()
-                    .map(|dir_entry| {
-                        let name = dir_entry.unwrap().file_name().into_string().unwrap();
-                        name
-                    })
-                    .collect();
+                // --manifest: 导入后在 /.manifest 中记录打包清单, 其余参数为导入策略
+                let args: Vec<&str> = input.collect();
+                let record_manifest = args.contains(&"--manifest");
+                let policy = match host::ImportPolicy::parse(
+                    args.iter().copied().filter(|&arg| arg != "--manifest"),
+                ) {
+                    Ok(policy) => policy,
+                    Err(err) => {
+                        println!("🦀 set: {} 🦐", err);
+                        continue;
+                    }
+                };
+                // 先筛选出要导入的文件, 符号链接/特殊文件/超大文件按策略处理
+                let max_len = host::max_import_len(&efs, &curr_folder_inode);
+                let files = match host::plan_import(src_path, &policy, max_len) {
+                    Ok(files) => files,
+                    Err(err) => {
+                        println!("🦀 set: {}, nothing imported! 🦐", err);
+                        continue;
+                    }
+                };
+
+                // 写入任何内容之前先预留所有文件需要的索引节点与数据块 (以及目录项需要的块),
+                // 空间不足时直接放弃, 避免镜像中只导入了一半
+                let mut blocks = curr_folder_inode
+                    .blocks_needed(curr_folder_inode.size() + files.len() * fs::DIRENT_SIZE);
+                for file in files.iter() {
+                    blocks += fs::DiskInode::total_blocks(file.len as u32) as usize;
+                }
+                let reservation =
+                    FileSystem::reserve_inodes(&efs, files.len()).and_then(|mut reservation| {
+                        FileSystem::reserve_blocks(&efs, blocks).map(|blocks| {
+                            reservation.merge(blocks);
+                            reservation
+                        })
+                    });
+                if reservation.is_none() {
+                    println!(
+                        "🦀 set: No space left for {} files ({} blocks), nothing imported! 🦐",
+                        files.len(),
+                        blocks
+                    );
+                    continue;
+                }
+                let mut reservation = reservation.unwrap();
 
+                let mut imported = 0;
                 for file in files {
                     // 从host文件系统中读取文件
-                    println!("🐳 Set {}{} to easy-fs.", src_path, file);
-                    let mut host_file = File::open(format!("{}{}", src_path, file)).unwrap();
-                    let mut all_data: Vec<u8> = Vec::new();
-                    host_file.read_to_end(&mut all_data).unwrap();
+                    println!("🐳 Set {} to easy-fs.", file.path);
+                    let mut host_file = match File::open(&file.path) {
+                        Ok(host_file) => host_file,
+                        Err(err) => {
+                            println!("🍡 skip {}: {}", file.name, err);
+                            continue;
+                        }
+                    };
                     // 创建文件
-                    let inode = curr_folder_inode.create(file.as_str(), fs::DiskInodeType::File);
+                    let inode = curr_folder_inode.create_reserved(
+                        file.name.as_str(),
+                        fs::DiskInodeType::File,
+                        &mut reservation,
+                    );
                     if inode.is_some() {
-                        // 写入文件
+                        // 写入文件 (只搬运数据段, 跳过 host 文件中的空洞)
                         let inode = inode.unwrap();
-                        inode.write(0, all_data.as_slice());
+                        match host::import_file(&mut host_file, &inode, file.len, &mut reservation)
+                        {
+                            Ok(_) => imported += 1,
+                            Err(err) => println!("🍡 {}: {}", file.name, err),
+                        }
                     }
                 }
+
+                if record_manifest {
+                    // 先归还没有用完的预留, 清单本身不在预留的空间中
+                    drop(reservation);
+                    let manifest = Manifest {
+                        tool: format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+                        source: src_path.to_string(),
+                        timestamp: fs::timestamp_now(),
+                        files: imported,
+                        allocator: allocator.to_string(),
+                        options: args.join(" "),
+                    };
+                    if let Err(err) = manifest.save(&root_inode) {
+                        println!("🦀 set: Cannot record the manifest, {}! 🦐", err);
+                    }
+                }
+            }
+
+            // inspect --provenance: 查看 set --manifest 记录的打包清单
+            "inspect" => {
+                if input.next() != Some("--provenance") {
+                    help::find("inspect").unwrap().usage_error("Unknown option");
+                    continue;
+                }
+                match Manifest::load(&root_inode) {
+                    Some(manifest) => {
+                        println!("🐳 tool:      {}", manifest.tool);
+                        println!("🐳 source:    {}", manifest.source);
+                        println!("🐳 packed at: {}", format_time(manifest.timestamp));
+                        println!("🐳 files:     {}", manifest.files);
+                        println!("🐳 allocator: {}", manifest.allocator);
+                        println!("🐳 options:   {}", manifest.options);
+                    }
+                    None => println!(
+                        "🦀 inspect: No manifest recorded, pack with \"set --manifest\"! 🦐"
+                    ),
+                }
             }
 
             // 清空文件系统
@@ -558,45 +816,165 @@ fn fs_pack() -> std::io::Result<()> {
                 }
             }
 
+            // 挂载以来的统计信息
+            "stats" => {
+                let stats = efs.lock().statistics();
+                println!(
+                    "🐳 cache: {} hits, {} misses, {} evictions.",
+                    stats.cache.hits, stats.cache.misses, stats.cache.evictions
+                );
+                // 每次缓存未命中恰好读一次块设备
+                println!(
+                    "🐳 device: {} block reads, {} block writes.",
+                    stats.cache.misses, stats.cache.block_writes
+                );
+                println!(
+                    "🐳 alloc: {} inodes ({} freed), {} data blocks ({} freed).",
+                    stats.alloc.inode_allocs,
+                    stats.alloc.inode_deallocs,
+                    stats.alloc.data_allocs,
+                    stats.alloc.data_deallocs
+                );
+                println!(
+                    "🐳 ops: find {}, ls {}, create {}, read {}, write {}, clear {}, remove {}, rename {}.",
+                    stats.ops.find,
+                    stats.ops.ls,
+                    stats.ops.create,
+                    stats.ops.read,
+                    stats.ops.write,
+                    stats.ops.clear,
+                    stats.ops.remove,
+                    stats.ops.rename
+                );
+            }
+
+            // alias: 列出所有别名; alias name='command args': 定义别名
+            "alias" => {
+                let definition = input.collect::<Vec<_>>().join(" ");
+                if definition.is_empty() {
+                    for (name, command) in aliases.iter() {
+                        println!("alias {}='{}'", name, command);
+                    }
+                } else if aliases.define(&definition) {
+                    if let Err(err) = aliases.save(&root_inode) {
+                        println!("🦀 alias: Cannot save aliases, {}! 🦐", err);
+                    }
+                } else {
+                    println!("🦀 alias: usage: alias name='command args' 🦐");
+                }
+            }
+
+            "unalias" => {
+                let name = input.next();
+                if name.is_none() {
+                    println!("🦀 unalias: Miss alias name! 🦐");
+                    continue;
+                }
+                if aliases.remove(name.unwrap()) {
+                    if let Err(err) = aliases.save(&root_inode) {
+                        println!("🦀 unalias: Cannot save aliases, {}! 🦐", err);
+                    }
+                } else {
+                    println!("🦀 unalias: No such alias: {}! 🦐", name.unwrap());
+                }
+            }
+
+            // 镜像空间占用: 元数据开销与文件内容各占多少
+            "usage" => {
+                let usage = fs::Usage::collect(&efs);
+                let show = |name: &str, blocks: usize| {
+                    println!(
+                        "🐳 {:<14}{:>8} blocks {:>10} KiB {:>6.2}%",
+                        name,
+                        blocks,
+                        blocks * BLOCK_SIZE / 1024,
+                        blocks as f64 * 100.0 / usage.total_blocks as f64
+                    );
+                };
+                show("super block", usage.super_blocks);
+                show("inode bitmap", usage.inode_bitmap_blocks);
+                show("inode area", usage.inode_area_blocks);
+                show("data bitmap", usage.data_bitmap_blocks);
+                show("index blocks", usage.index_blocks);
+                show("dir entries", usage.dir_blocks);
+                show("file data", usage.file_blocks);
+                if usage.merkle_blocks > 0 {
+                    show("merkle tables", usage.merkle_blocks);
+                }
+                show("free", usage.free_blocks);
+                if usage.leaked_blocks > 0 {
+                    show("leaked", usage.leaked_blocks);
+                }
+                show("total", usage.total_blocks);
+                println!(
+                    "🐳 inodes: {} / {} used.",
+                    usage.inodes_used, usage.inodes_total
+                );
+                for (name, blocks) in usage.top_level.iter() {
+                    println!(
+                        "   🍡 {:<28}{:>8} blocks {:>10} KiB",
+                        name,
+                        blocks,
+                        blocks * BLOCK_SIZE / 1024
+                    );
+                }
+            }
+
+            // merkle on/off: 开启或关闭 Merkle 模式
+            "merkle" => match input.next() {
+                Some("on") => match efs.lock().merkle_on() {
+                    Some(root) => println!("🐳 merkle: root {}. 🐬", fs::merkle_hex(&root)),
+                    None => println!("🦀 merkle: No space left for the hash tables! 🦐"),
+                },
+                Some("off") => efs.lock().merkle_off(),
+                _ => println!("🦀 merkle: usage: merkle on/off 🦐"),
+            },
+
+            // verify --merkle: 从磁盘内容重新计算 Merkle 根哈希并与超级块中保存的比较, 不会修改保存的根哈希
+            "verify" => {
+                if input.next() != Some("--merkle") {
+                    println!("🦀 verify: usage: verify --merkle 🦐");
+                    continue;
+                }
+                let verified = efs.lock().merkle_verify();
+                match verified {
+                    None => println!("🦀 verify: merkle mode is off, use \"merkle on\" first! 🦐"),
+                    Some((stored, root, _)) if stored == root => {
+                        println!("🐳 verify: OK, root {}. 🐬", fs::merkle_hex(&root))
+                    }
+                    Some((stored, root, mismatched)) => {
+                        println!("🦀 verify: MISMATCH! 🦐");
+                        println!("   🍡 stored:   {}", fs::merkle_hex(&stored));
+                        println!("   🍡 computed: {}", fs::merkle_hex(&root));
+                        for path in mismatched {
+                            println!("   🍡 modified: {}", path);
+                        }
+                    }
+                }
+            }
+
             "exit" => {
+                // 等待预取结束, 预取线程可能正持有块缓存的锁
+                if let Some(handle) = prefetch.take() {
+                    let _ = handle.join();
+                }
                 block_cache_sync_all(); // fix bug: when exit, the data in block cache will not be written to disk
                 break;
             }
 
-            "help" => {
-                println!("🐳 help: show helps.\n");
-                println!("🐳 ls: list all files in current folder.\n");
-                println!("🐳 cd: change current folder.\n");
-                println!("🐳 cat: print file content.\n");
-                println!("🐳 touch: create a file.\n");
-                println!("🐳 mkdir: create a folder.\n");
-                println!("🐳 stat: show file or folder stat.\n");
-                println!("🐳 get: a test of fs, getting files to host form root directory.\n");
-                println!("🐳 set: a test of fs, setting host files (src files of fs) to root directory.\n");
-                println!("🐳 fmt: format easy-fs.\n");
-                println!("🐳 exit: exit easy-fs.\n");
-
-                println!("🐳 chname: change file or folder name.");
-                println!("   🍡 usage: chname old_name new_name");
-                println!("   🍡 note: the length of new_name is expected to be less than 27 ascii characters,");
-                println!("          or no more than 9 unicode characters.");
-                println!();
-
-                println!("🐳 rm: remove files or folders.");
-                println!("   🍡 usage: rm file1 folder2 file3 ...\n");
-
-                println!("🐳 write: write content to file.");
-                println!("   🍡 usage: write file_name (offset or \"-a\") content");
-                println!("   🍡 offset: write content to file from offset.");
-                println!("   🍡 -a: append content to file.");
-                println!("   🍡 note: contents end with newline EOF.\n");
-
-                println!("🐳 read: read content from file.");
-                println!("   🍡 usage: read file_name (offset) (length)");
-                println!("   🍡 offset: read content from file from offset.");
-                println!("   🍡 length: read content length.");
-                println!("   🍡 if offset and length are not set, read all content.\n");
-            }
+            // help: 列出所有命令; help command: 输出该命令的用法
+            "help" => match input.next() {
+                Some(name) => match help::find(name) {
+                    Some(spec) => spec.print_help(),
+                    None => println!("🦀 help: Unknown command: {}! 🦐", name),
+                },
+                None => {
+                    for spec in help::COMMANDS {
+                        println!("🐳 {:<8} {}", spec.name, spec.summary);
+                    }
+                    println!("\n🐳 use \"help command\" to show the usage of a command. 🐬");
+                }
+            },
             _ => println!("🦀 Unknown command: {}! 🦐", cmd),
         }
     }
@@ -646,3 +1024,27 @@ fn update_path(target: &str) {
         }
     }
 }
+
+/// 解析 touch -d 的时间参数 (本地时间): "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d" 或 "@秒数"
+fn parse_time(time: &str) -> Option<u32> {
+    if let Some(secs) = time.strip_prefix('@') {
+        return secs.parse::<u32>().ok();
+    }
+    let datetime = NaiveDateTime::parse_from_str(time, "%Y-%m-%dT%H:%M:%S")
+        .ok()
+        .or_else(|| {
+            NaiveDate::parse_from_str(time, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })?;
+    let timestamp = Local.from_local_datetime(&datetime).earliest()?.timestamp();
+    u32::try_from(timestamp).ok()
+}
+
+/// 将时间戳格式化为本地时间
+fn format_time(time: u32) -> String {
+    match Local.timestamp_opt(time as i64, 0).single() {
+        Some(datetime) => datetime.format("%Y-%m-%d %H:%M:%S").to_string(),
+        None => format!("@{}", time),
+    }
+}
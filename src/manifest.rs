@@ -0,0 +1,61 @@
+//! 打包清单: set --manifest 时记录镜像是如何打包出来的
+//!
+//! 清单保存在镜像根目录的 /.manifest 中 (每行一条 key=value),
+//! 部署出去的镜像可以通过 inspect --provenance 查看自己的来源, 而不需要额外的说明文件.
+
+use crate::fs::Inode;
+
+/// 保存打包清单的文件 (位于根目录)
+pub const MANIFEST: &str = ".manifest";
+
+#[derive(Debug, Default)]
+pub struct Manifest {
+    /// 打包工具及其版本
+    pub tool: String,
+    /// host 上的源目录
+    pub source: String,
+    /// 打包时间 (秒)
+    pub timestamp: u32,
+    /// 导入的文件数量
+    pub files: usize,
+    /// 数据块分配策略
+    pub allocator: String,
+    /// set 的参数
+    pub options: String,
+}
+
+impl Manifest {
+    /// 从根目录的 /.manifest 读入清单, 文件不存在 (或者是目录) 时返回 None
+    pub fn load(root_inode: &Inode) -> Option<Self> {
+        let file = root_inode.find(MANIFEST).filter(|file| !file.is_dir())?;
+        let mut buf = vec![0u8; file.size()];
+        file.read(0, &mut buf);
+        let mut manifest = Self::default();
+        for line in String::from_utf8_lossy(&buf).lines() {
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim().to_string()),
+                None => continue,
+            };
+            match key {
+                "tool" => manifest.tool = value,
+                "source" => manifest.source = value,
+                "timestamp" => manifest.timestamp = value.parse().unwrap_or(0),
+                "files" => manifest.files = value.parse().unwrap_or(0),
+                "allocator" => manifest.allocator = value,
+                "options" => manifest.options = value,
+                // 忽略不认识的字段, 以便以后增加字段
+                _ => {}
+            }
+        }
+        Some(manifest)
+    }
+
+    /// 将清单写入根目录的 /.manifest, 覆盖之前的清单, /.manifest 是目录时返回错误
+    pub fn save(&self, root_inode: &Inode) -> Result<(), String> {
+        let content = format!(
+            "tool={}\nsource={}\ntimestamp={}\nfiles={}\nallocator={}\noptions={}\n",
+            self.tool, self.source, self.timestamp, self.files, self.allocator, self.options
+        );
+        root_inode.replace_file(MANIFEST, content.as_bytes())
+    }
+}
@@ -0,0 +1,205 @@
+//! 打包前检查后端存储 (-w preflight)
+//!
+//! BlockFile 在读写失败或者读写不完整时直接 panic, 打包到一半才发现目标目录不可写,
+//! 磁盘空间不足或者文件系统不支持随机读写就为时已晚. preflight 在创建镜像之前用一个临时文件检查:
+//!
+//! - 目标目录是否可写
+//! - host 磁盘的剩余空间是否足够容纳镜像
+//! - seek 之后按块读写是否完整 (BlockFile 要求一次 read/write 就读写完整的一个块)
+//! - 顺序/随机读写的吞吐量
+
+use std::{
+    fs::{metadata, remove_file, File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    time::Instant,
+};
+
+use crate::fs::BLOCK_SIZE;
+
+/// 用于检查的临时文件名
+const PROBE: &str = ".preflight.probe";
+/// 临时文件的块数 (1 MiB)
+const PROBE_BLOCKS: usize = 2048;
+
+/// 检查 target_path 能否存放 image_size 字节的镜像, 全部通过时返回 true
+pub fn preflight(target_path: &str, image_size: u64) -> bool {
+    println!("🐳 Preflight check of {} 🐬", target_path);
+    let probe_path = format!("{}{}", target_path, PROBE);
+    let passed = check(target_path, &probe_path, image_size);
+    let _ = remove_file(&probe_path);
+    match &passed {
+        Ok(()) => println!("🐳 Preflight passed, ready to pack. 🐬"),
+        Err(err) => println!("🦀 Preflight failed: {} 🦐", err),
+    }
+    passed.is_ok()
+}
+
+fn check(target_path: &str, probe_path: &str, image_size: u64) -> Result<(), String> {
+    // 目标目录
+    match metadata(target_path) {
+        Ok(meta) if meta.is_dir() => {}
+        Ok(_) => return Err(format!("{} is not a directory", target_path)),
+        Err(err) => {
+            return Err(format!(
+                "{}: {}, create it first or pass another -t",
+                target_path, err
+            ))
+        }
+    }
+    let mut probe = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(probe_path)
+        .map_err(|err| {
+            format!(
+                "{} is not writable ({}), check its permissions or pass another -t",
+                target_path, err
+            )
+        })?;
+    println!("   🍡 writable: ok");
+
+    // 剩余空间, 已经存在的镜像会被复用, 只需要补上差值
+    let existing = metadata(format!("{}fs.img", target_path))
+        .map(|meta| meta.len())
+        .unwrap_or(0);
+    let needed = image_size.saturating_sub(existing);
+    match available_space(target_path) {
+        Some(available) if available < needed => {
+            return Err(format!(
+                "{} bytes available but {} needed, free up {} bytes or pass another -t",
+                available,
+                needed,
+                needed - available
+            ))
+        }
+        Some(available) => println!(
+            "   🍡 free space: ok ({} bytes available, {} needed)",
+            available, needed
+        ),
+        None => println!("   🍡 free space: unknown on this platform, skipped"),
+    }
+
+    // seek 与不完整的读写
+    check_blocks(&mut probe)?;
+    println!("   🍡 seek and block read/write: ok");
+
+    // 吞吐量. 刚写入的数据还在 host 的页缓存中, 读的吞吐量只能作为参考
+    let (seq_write, seq_read) = sequential(&mut probe).map_err(|err| err.to_string())?;
+    let (rand_write, rand_read) = random(&mut probe).map_err(|err| err.to_string())?;
+    println!(
+        "   🍡 sequential: write {:.1} MiB/s, read {:.1} MiB/s",
+        seq_write, seq_read
+    );
+    println!(
+        "   🍡 random: write {:.1} MiB/s, read {:.1} MiB/s",
+        rand_write, rand_read
+    );
+    Ok(())
+}
+
+/// host 文件系统中 path 所在分区对非特权用户可用的字节数
+#[cfg(unix)]
+fn available_space(path: &str) -> Option<u64> {
+    let path = std::ffi::CString::new(path).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+fn available_space(_path: &str) -> Option<u64> {
+    None
+}
+
+/// 与 BlockFile 一样 seek 后一次 read/write 一个块, 检查读写是否完整, 读回的内容是否一致
+fn check_blocks(probe: &mut File) -> Result<(), String> {
+    probe
+        .set_len((PROBE_BLOCKS * BLOCK_SIZE) as u64)
+        .map_err(|err| format!("cannot resize the probe file: {}", err))?;
+    // 倒序写入, 每个块填充自己的编号, 保证写入依赖 seek 而不是顺序追加
+    let mut buf = [0u8; BLOCK_SIZE];
+    for block_id in (0..PROBE_BLOCKS).rev() {
+        buf.fill(block_id as u8);
+        probe
+            .seek(SeekFrom::Start((block_id * BLOCK_SIZE) as u64))
+            .map_err(|err| format!("seek failed: {}", err))?;
+        let written = probe
+            .write(&buf)
+            .map_err(|err| format!("write failed: {}", err))?;
+        if written != BLOCK_SIZE {
+            return Err(format!(
+                "short write ({} of {} bytes), the image would be corrupted",
+                written, BLOCK_SIZE
+            ));
+        }
+    }
+    for block_id in 0..PROBE_BLOCKS {
+        probe
+            .seek(SeekFrom::Start((block_id * BLOCK_SIZE) as u64))
+            .map_err(|err| format!("seek failed: {}", err))?;
+        let read = probe
+            .read(&mut buf)
+            .map_err(|err| format!("read failed: {}", err))?;
+        if read != BLOCK_SIZE {
+            return Err(format!(
+                "short read ({} of {} bytes), the backing store does not return whole blocks",
+                read, BLOCK_SIZE
+            ));
+        }
+        if buf.iter().any(|&byte| byte != block_id as u8) {
+            return Err(format!("block {} reads back different data", block_id));
+        }
+    }
+    Ok(())
+}
+
+/// 换算成 MiB/s
+fn throughput(blocks: usize, start: Instant) -> f64 {
+    let secs = start.elapsed().as_secs_f64().max(1e-9);
+    (blocks * BLOCK_SIZE) as f64 / (1024.0 * 1024.0) / secs
+}
+
+/// 顺序写 (包括 sync) 与顺序读的吞吐量
+fn sequential(probe: &mut File) -> std::io::Result<(f64, f64)> {
+    let mut buf = [0x5au8; BLOCK_SIZE];
+    probe.seek(SeekFrom::Start(0))?;
+    let start = Instant::now();
+    for _ in 0..PROBE_BLOCKS {
+        probe.write_all(&buf)?;
+    }
+    probe.sync_all()?;
+    let write = throughput(PROBE_BLOCKS, start);
+
+    probe.seek(SeekFrom::Start(0))?;
+    let start = Instant::now();
+    for _ in 0..PROBE_BLOCKS {
+        probe.read_exact(&mut buf)?;
+    }
+    Ok((write, throughput(PROBE_BLOCKS, start)))
+}
+
+/// 随机写 (包括 sync) 与随机读的吞吐量
+fn random(probe: &mut File) -> std::io::Result<(f64, f64)> {
+    let block_ids: Vec<usize> = (0..PROBE_BLOCKS)
+        .map(|_| rand::random::<usize>() % PROBE_BLOCKS)
+        .collect();
+    let mut buf = [0xa5u8; BLOCK_SIZE];
+    let start = Instant::now();
+    for &block_id in block_ids.iter() {
+        probe.seek(SeekFrom::Start((block_id * BLOCK_SIZE) as u64))?;
+        probe.write_all(&buf)?;
+    }
+    probe.sync_all()?;
+    let write = throughput(PROBE_BLOCKS, start);
+
+    let start = Instant::now();
+    for &block_id in block_ids.iter().rev() {
+        probe.seek(SeekFrom::Start((block_id * BLOCK_SIZE) as u64))?;
+        probe.read_exact(&mut buf)?;
+    }
+    Ok((write, throughput(PROBE_BLOCKS, start)))
+}
@@ -1,17 +1,31 @@
 #![allow(unused)]
 use super::device;
 use super::fs;
+use crate::alias::{Aliases, PROFILE};
 use crate::fs::DirEntry;
+use crate::host;
+use crate::manifest::{Manifest, MANIFEST};
 use crate::BLOCK_NUM;
 use device::BlockFile;
 use fs::{BlockDevice, FileSystem, BLOCK_SIZE};
-use std::fs::OpenOptions;
-use std::sync::{Arc, Mutex};
+use lazy_static::*;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
 
-#[test]
-fn fs_test() -> std::io::Result<()> {
-    // 创建虚拟磁盘
-    let block_file = Arc::new(BlockFile(Mutex::new({
+lazy_static! {
+    /// 块缓存是全局的, 并且只以块编号区分, 所以测试之间不能并行, 都使用同一个镜像 target/fs.img
+    static ref FS_TEST_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// 获取测试锁, 某个测试 panic 之后其余测试仍然可以继续
+fn lock_fs_test() -> MutexGuard<'static, ()> {
+    FS_TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// 打开 target/fs.img 作为虚拟磁盘
+fn open_block_file() -> std::io::Result<Arc<BlockFile>> {
+    Ok(Arc::new(BlockFile(Mutex::new({
         // 创建文件, 设置权限
         let f = OpenOptions::new()
             .read(true)
@@ -21,7 +35,19 @@ fn fs_test() -> std::io::Result<()> {
         // 设置文件大小
         f.set_len((BLOCK_NUM * BLOCK_SIZE) as u64).unwrap();
         f
-    })));
+    }))))
+}
+
+/// 在 target/fs.img 上创建一个与 shell 默认参数相同的文件系统
+fn create_default_fs() -> std::io::Result<Arc<spin::Mutex<FileSystem>>> {
+    Ok(FileSystem::create(open_block_file()?, BLOCK_NUM as u32, 1))
+}
+
+#[test]
+fn fs_test() -> std::io::Result<()> {
+    let _guard = lock_fs_test();
+    // 创建虚拟磁盘
+    let block_file = open_block_file()?;
 
     // 在虚拟块设备 block_file 上初始化 easy-fs 文件系统
     FileSystem::create(block_file.clone(), 4096, 1);
@@ -82,3 +108,744 @@ fn fs_test() -> std::io::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn statistics_test() -> std::io::Result<()> {
+    let _guard = lock_fs_test();
+    let efs = create_default_fs()?;
+    let block_device: Arc<dyn BlockDevice> = Arc::clone(&efs.lock().block_device);
+
+    // 格式化产生的缓存与分配计数不计入统计, 挂载之后从 0 开始
+    let check_zero = |stats: fs::Statistics| {
+        let cache = stats.cache;
+        assert_eq!(
+            (
+                cache.hits,
+                cache.misses,
+                cache.evictions,
+                cache.block_writes
+            ),
+            (0, 0, 0, 0)
+        );
+        let alloc = stats.alloc;
+        assert_eq!(
+            (
+                alloc.inode_allocs,
+                alloc.inode_deallocs,
+                alloc.data_allocs,
+                alloc.data_deallocs
+            ),
+            (0, 0, 0, 0)
+        );
+        let ops = stats.ops;
+        assert_eq!((ops.find, ops.ls, ops.create, ops.read), (0, 0, 0, 0));
+        assert_eq!((ops.write, ops.clear, ops.remove, ops.rename), (0, 0, 0, 0));
+    };
+    check_zero(efs.lock().statistics());
+
+    // 一系列已知的操作
+    let root_inode = Arc::new(FileSystem::root_inode(&efs));
+    let file = root_inode.create("file", fs::DiskInodeType::File).unwrap();
+    file.write(0, &[0x5au8; 3 * BLOCK_SIZE]);
+    let mut buf = [0u8; BLOCK_SIZE];
+    assert_eq!(file.read(BLOCK_SIZE, &mut buf), BLOCK_SIZE);
+    assert!(root_inode.find("file").is_some());
+    assert_eq!(root_inode.ls(), vec!["file"]);
+    root_inode.chname("file", "renamed");
+    file.clear();
+    file.rm_dir_entry("renamed", Arc::clone(&root_inode));
+
+    let stats = efs.lock().statistics();
+    let alloc = stats.alloc;
+    // 根目录的目录项一个块, 文件内容三个块, rm 不回收目录项所在的块和 inode
+    assert_eq!(
+        (
+            alloc.inode_allocs,
+            alloc.inode_deallocs,
+            alloc.data_allocs,
+            alloc.data_deallocs
+        ),
+        (1, 0, 4, 3)
+    );
+    let ops = stats.ops;
+    assert_eq!((ops.find, ops.ls, ops.create, ops.read), (1, 1, 1, 1));
+    assert_eq!((ops.write, ops.clear, ops.remove, ops.rename), (1, 1, 1, 1));
+    let cache = stats.cache;
+    assert!(cache.hits + cache.misses > 0);
+    assert!(cache.block_writes > 0);
+    // 快照之间的差值就是这段时间内的计数
+    let later = efs.lock().statistics().cache.since(&cache);
+    assert_eq!((later.hits, later.misses, later.block_writes), (0, 0, 0));
+
+    // 重新挂载之后同样从 0 开始
+    drop(file);
+    drop(root_inode);
+    let efs = FileSystem::open(block_device);
+    check_zero(efs.lock().statistics());
+    Ok(())
+}
+
+#[test]
+fn usage_test() -> std::io::Result<()> {
+    let _guard = lock_fs_test();
+    let efs = create_default_fs()?;
+    let root_inode = Arc::new(FileSystem::root_inode(&efs));
+    let dir = root_inode
+        .create("dir", fs::DiskInodeType::Directory)
+        .unwrap();
+    dir.create("a", fs::DiskInodeType::File)
+        .unwrap()
+        .write(0, &[1u8; 3 * BLOCK_SIZE]);
+    // 超过直接索引的范围, 需要一个一级索引块
+    root_inode
+        .create("b", fs::DiskInodeType::File)
+        .unwrap()
+        .write(0, &[2u8; 100 * BLOCK_SIZE]);
+    let c = root_inode.create("c", fs::DiskInodeType::File).unwrap();
+    c.write(0, &[3u8; 50 * BLOCK_SIZE]);
+    c.clear();
+    c.rm_dir_entry("c", Arc::clone(&root_inode));
+
+    let check = |merkle_blocks: usize| {
+        let usage = fs::Usage::collect(&efs);
+        assert_eq!(
+            usage.super_blocks
+                + usage.inode_bitmap_blocks
+                + usage.inode_area_blocks
+                + usage.data_bitmap_blocks
+                + usage.index_blocks
+                + usage.dir_blocks
+                + usage.file_blocks
+                + usage.merkle_blocks
+                + usage.free_blocks
+                + usage.leaked_blocks,
+            usage.total_blocks
+        );
+        assert_eq!(usage.total_blocks, BLOCK_NUM);
+        assert_eq!(usage.leaked_blocks, 0);
+        assert_eq!(usage.merkle_blocks, merkle_blocks);
+        assert_eq!(usage.free_blocks, efs.lock().free_data_blocks());
+        assert_eq!((usage.index_blocks, usage.file_blocks), (1, 103));
+        // 根目录与 dir 的目录项各一个块
+        assert_eq!(usage.dir_blocks, 2);
+        assert_eq!(
+            usage.top_level,
+            vec![("dir".to_string(), 4), ("b".to_string(), 101)]
+        );
+        // 每一项的总数加上根目录自身占用的块, 等于所有文件/目录占用的块
+        let (root_data, root_index) = root_inode.block_usage();
+        assert_eq!(
+            usage
+                .top_level
+                .iter()
+                .map(|(_, blocks)| blocks)
+                .sum::<usize>()
+                + root_data
+                + root_index,
+            usage.index_blocks + usage.dir_blocks + usage.file_blocks
+        );
+        assert_eq!(usage.inodes_used, 5 + if merkle_blocks > 0 { 2 } else { 0 });
+        assert_eq!(usage.inodes_total, efs.lock().inode_bitmap.maximum());
+    };
+    check(0);
+
+    // 开启 Merkle 模式后, 节点表与叶子表单独统计, 不算作泄漏
+    efs.lock().merkle_on().unwrap();
+    let merkle_blocks = efs.lock().merkle_table_blocks();
+    assert!(merkle_blocks > 0);
+    check(merkle_blocks);
+    efs.lock().merkle_off();
+    check(0);
+    Ok(())
+}
+
+#[test]
+fn timestamp_test() -> std::io::Result<()> {
+    let _guard = lock_fs_test();
+    let efs = create_default_fs()?;
+    let root_inode = Arc::new(FileSystem::root_inode(&efs));
+
+    // 新建的文件使用当前时间, set_times 之后原样读回
+    let file = root_inode.create("file", fs::DiskInodeType::File).unwrap();
+    let (atime, mtime) = file.times();
+    assert!(atime > 0 && mtime > 0);
+    file.set_times(1680307200, 1680310800);
+    assert_eq!(file.times(), (1680307200, 1680310800));
+    // 写入文件更新 mtime
+    file.write(0, b"Hello, world!");
+    assert!(file.times().1 > 1680310800);
+
+    // 目录项增加/删除/改名时更新目录的 mtime
+    let dir = root_inode
+        .create("dir", fs::DiskInodeType::Directory)
+        .unwrap();
+    dir.set_times(0, 0);
+    let child = dir.create("child", fs::DiskInodeType::File).unwrap();
+    assert!(dir.times().1 > 0);
+    dir.set_times(0, 0);
+    dir.chname("child", "renamed");
+    assert!(dir.times().1 > 0);
+    dir.set_times(0, 0);
+    child.rm_dir_entry("renamed", Arc::clone(&dir));
+    assert!(dir.times().1 > 0);
+    assert!(dir.ls().is_empty());
+    // 改名不存在的文件时目录不变
+    dir.set_times(0, 0);
+    dir.chname("missing", "other");
+    assert_eq!(dir.times(), (0, 0));
+    Ok(())
+}
+
+#[test]
+fn parse_time_test() {
+    use crate::{format_time, parse_time};
+    assert_eq!(parse_time("@0"), Some(0));
+    assert_eq!(parse_time("@1680307200"), Some(1680307200));
+    assert_eq!(parse_time("@4294967295"), Some(u32::MAX));
+    // 日期与日期时间按本地时间解析, 格式化之后得到同样的时间
+    let datetime = parse_time("2023-04-01T08:00:00").unwrap();
+    assert_eq!(format_time(datetime), "2023-04-01 08:00:00");
+    let date = parse_time("2023-04-01").unwrap();
+    assert_eq!(format_time(date), "2023-04-01 00:00:00");
+    assert_eq!(datetime - date, 8 * 3600);
+    for time in [
+        "",
+        "@",
+        "@-1",
+        "@4294967296",
+        "@1.5",
+        "2023-04-01 08:00:00",
+        "2023-13-01",
+        "2023-02-30",
+        "2023-04-01T25:00:00",
+        "1960-01-01",
+        "yesterday",
+    ] {
+        assert_eq!(parse_time(time), None, "{}", time);
+    }
+}
+
+#[test]
+fn reservation_test() -> std::io::Result<()> {
+    let _guard = lock_fs_test();
+    let efs = create_default_fs()?;
+    let root_inode = FileSystem::root_inode(&efs);
+    let greet_str = "Hello, world!";
+    let free_blocks = efs.lock().free_data_blocks();
+
+    // 预留的索引节点和数据块优先被 create_reserved/write_reserved 消耗, 剩余部分在 drop 时归还
+    let mut reservation = FileSystem::reserve_inodes(&efs, 1).unwrap();
+    reservation.merge(FileSystem::reserve_blocks(&efs, 3).unwrap());
+    let file = root_inode
+        .create_reserved("file", fs::DiskInodeType::File, &mut reservation)
+        .unwrap();
+    file.write_reserved(0, greet_str.as_bytes(), &mut reservation);
+    // 根目录的目录项与文件内容各用了一个块
+    assert_eq!((reservation.inodes(), reservation.blocks()), (0, 1));
+    assert!(FileSystem::reserve_blocks(&efs, free_blocks - 2).is_none());
+    drop(reservation);
+    assert_eq!(efs.lock().free_data_blocks(), free_blocks - 2);
+    assert!(FileSystem::reserve_blocks(&efs, free_blocks - 2).is_some());
+
+    // 空闲的索引节点/数据块都被预留出去时, 创建与写入失败而不是 panic, 也不会留下分配了一半的数据块
+    let free_blocks = efs.lock().free_data_blocks();
+    let free_inodes = efs.lock().free_inodes();
+    let reservation = FileSystem::reserve_blocks(&efs, free_blocks).unwrap();
+    assert_eq!(file.write(BLOCK_SIZE * 30, greet_str.as_bytes()), 0);
+    assert_eq!(file.size(), greet_str.len());
+    assert_eq!(efs.lock().free_data_blocks(), free_blocks);
+    drop(reservation);
+    let reservation = FileSystem::reserve_inodes(&efs, free_inodes).unwrap();
+    assert!(root_inode
+        .create("other", fs::DiskInodeType::File)
+        .is_none());
+    drop(reservation);
+    assert_eq!(efs.lock().free_inodes(), free_inodes);
+
+    // 与 set 一样先预留所有空间, 数据块不够时已经预留的索引节点随之归还, 什么都没有创建
+    let reservation = FileSystem::reserve_inodes(&efs, 2).and_then(|mut reservation| {
+        FileSystem::reserve_blocks(&efs, free_blocks + 1).map(|blocks| {
+            reservation.merge(blocks);
+            reservation
+        })
+    });
+    assert!(reservation.is_none());
+    assert_eq!(efs.lock().free_inodes(), free_inodes);
+    assert_eq!(efs.lock().free_data_blocks(), free_blocks);
+    assert!(FileSystem::reserve_inodes(&efs, free_inodes).is_some());
+
+    // 预留用完之后 create_reserved 失败: 目录无法增长时归还已经分配的索引节点, 目录保持不变
+    let dir = root_inode
+        .create("dir", fs::DiskInodeType::Directory)
+        .unwrap();
+    let free_blocks = efs.lock().free_data_blocks();
+    let free_inodes = efs.lock().free_inodes();
+    let others = FileSystem::reserve_blocks(&efs, free_blocks).unwrap();
+    let mut reservation = FileSystem::reserve_inodes(&efs, 1).unwrap();
+    assert!(dir
+        .create_reserved("child", fs::DiskInodeType::File, &mut reservation)
+        .is_none());
+    drop(reservation);
+    drop(others);
+    assert!(dir.ls().is_empty());
+    assert_eq!(dir.size(), 0);
+    assert_eq!(efs.lock().free_inodes(), free_inodes);
+    assert_eq!(efs.lock().free_data_blocks(), free_blocks);
+    assert_eq!(root_inode.ls(), vec!["file", "dir"]);
+    Ok(())
+}
+
+#[test]
+fn inode_by_id_test() -> std::io::Result<()> {
+    let _guard = lock_fs_test();
+    let efs = create_default_fs()?;
+    let root_inode = FileSystem::root_inode(&efs);
+    let file = root_inode.create("file", fs::DiskInodeType::File).unwrap();
+    file.write(0, b"Hello, world!");
+
+    // 按编号打开 inode: 0 是根目录, file 是之后第一个分配的 inode, 未分配的编号返回 None
+    assert!(FileSystem::inode_by_id(&efs, 0).unwrap().is_dir());
+    let inode = FileSystem::inode_by_id(&efs, 1).unwrap();
+    assert!(!inode.is_dir());
+    let mut buffer = [0u8; 32];
+    let len = inode.read(0, &mut buffer);
+    assert_eq!(&buffer[..len], b"Hello, world!");
+    assert!(FileSystem::inode_by_id(&efs, 2).is_none());
+    assert!(FileSystem::inode_by_id(&efs, 100).is_none());
+    assert!(FileSystem::inode_by_id(&efs, u32::MAX).is_none());
+    Ok(())
+}
+
+#[test]
+fn hole_round_trip_test() -> std::io::Result<()> {
+    let _guard = lock_fs_test();
+    let efs = create_default_fs()?;
+    let root_inode = FileSystem::root_inode(&efs);
+    std::fs::create_dir_all("target/hole-test")?;
+
+    // (文件名, 文件大小, 写入数据的位置)
+    let cases: [(&str, u64, &[u64]); 3] = [
+        // 中间和末尾有数据, 其余都是空洞
+        ("sparse", 1 << 20, &[0, 300 * 1024, (1 << 20) - 7]),
+        // 以空洞结尾
+        ("hole_tail", 64 * 1024, &[1000]),
+        // 整个文件都是空洞
+        ("all_hole", 200 * 1024, &[]),
+    ];
+    for (name, len, offsets) in cases {
+        let path = format!("target/hole-test/{}", name);
+        let mut src = File::create(&path)?;
+        src.set_len(len)?;
+        for &offset in offsets {
+            src.seek(SeekFrom::Start(offset))?;
+            src.write_all(b"easy-fs")?;
+        }
+        src.sync_all()?;
+        let mut expected = Vec::new();
+        File::open(&path)?.read_to_end(&mut expected)?;
+
+        // 导入
+        let inode = root_inode.create(name, fs::DiskInodeType::File).unwrap();
+        let mut reservation = FileSystem::reserve_blocks(&efs, 0).unwrap();
+        let imported = host::import_file(&mut File::open(&path)?, &inode, len, &mut reservation)?;
+        drop(reservation);
+        assert_eq!(imported as u64, len);
+        assert_eq!(inode.size() as u64, len);
+
+        // 导出
+        let out_path = format!("target/hole-test/{}.out", name);
+        let mut out = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&out_path)?;
+        host::export_file(&inode, &mut out)?;
+        out.sync_all()?;
+        let mut actual = Vec::new();
+        File::open(&out_path)?.read_to_end(&mut actual)?;
+        assert_eq!(expected, actual, "{} differs after set/get", name);
+
+        // host 支持空洞时, 导出的文件同样是稀疏的 (占用的空间不超过源文件)
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let src_blocks = std::fs::metadata(&path)?.blocks();
+            let out_blocks = std::fs::metadata(&out_path)?.blocks();
+            if src_blocks * 512 < len {
+                assert!(out_blocks * 512 < len, "{} lost its holes", name);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn import_short_write_test() -> std::io::Result<()> {
+    let _guard = lock_fs_test();
+    let efs = create_default_fs()?;
+    let root_inode = FileSystem::root_inode(&efs);
+    std::fs::create_dir_all("target/import-test")?;
+
+    // (文件名, 文件大小, 写入数据的位置, 留给导入的空闲数据块)
+    let cases: [(&str, u64, &[u64], usize); 2] = [
+        // 数据段写不完
+        ("data", 8 * BLOCK_SIZE as u64, &[0], 1),
+        // 开头的数据段 (host 上最多一页) 写得下, 结尾空洞的最后一个字节写不下
+        ("hole_tail", 64 * 1024, &[0], 9),
+    ];
+    for (name, len, offsets, left) in cases {
+        let path = format!("target/import-test/{}", name);
+        let mut src = File::create(&path)?;
+        src.set_len(len)?;
+        for &offset in offsets {
+            src.seek(SeekFrom::Start(offset))?;
+            src.write_all(b"easy-fs")?;
+        }
+        if name == "data" {
+            src.write_all(&vec![0x5au8; len as usize - 7])?;
+        }
+        src.sync_all()?;
+
+        // 预留掉除了 left 个块之外的所有空闲数据块
+        let inode = root_inode.create(name, fs::DiskInodeType::File).unwrap();
+        let free = efs.lock().free_data_blocks();
+        let others = FileSystem::reserve_blocks(&efs, free - left).unwrap();
+        let mut reservation = FileSystem::reserve_blocks(&efs, 0).unwrap();
+        let err =
+            host::import_file(&mut File::open(&path)?, &inode, len, &mut reservation).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::WriteZero, "{}", name);
+        drop(reservation);
+        drop(others);
+        inode.clear();
+    }
+    Ok(())
+}
+
+#[test]
+fn import_policy_test() {
+    use host::{ImportPolicy, OversizePolicy, SymlinkPolicy};
+    let policy = ImportPolicy::parse(std::iter::empty()).unwrap();
+    assert_eq!(policy.symlinks, SymlinkPolicy::Follow);
+    assert_eq!(policy.oversize, OversizePolicy::Error);
+    let policy =
+        ImportPolicy::parse(["--symlinks=skip", "--oversize=truncate"].into_iter()).unwrap();
+    assert_eq!(policy.symlinks, SymlinkPolicy::Skip);
+    assert_eq!(policy.oversize, OversizePolicy::Truncate);
+    let policy = ImportPolicy::parse(["--oversize=skip", "--symlinks=follow"].into_iter()).unwrap();
+    assert_eq!(policy.symlinks, SymlinkPolicy::Follow);
+    assert_eq!(policy.oversize, OversizePolicy::Skip);
+    for args in [
+        "--symlinks=preserve",
+        "--symlinks=copy",
+        "--oversize=grow",
+        "--oversize",
+        "--force",
+    ] {
+        assert!(ImportPolicy::parse([args].into_iter()).is_err(), "{}", args);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn plan_import_test() -> std::io::Result<()> {
+    use host::{ImportEntry, ImportPolicy, OversizePolicy, SymlinkPolicy};
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt, os::unix::fs::symlink};
+    let _guard = lock_fs_test();
+
+    // 在 host 上准备各种条目
+    let src = "target/plan-test/";
+    let _ = std::fs::remove_dir_all(src);
+    std::fs::create_dir_all(src)?;
+    std::fs::write(format!("{}small", src), b"small")?;
+    File::create(format!("{}big", src))?.set_len(fs::MAX_FILE_SIZE as u64 + 1)?;
+    std::fs::write(
+        format!("{}{}", src, "n".repeat(fs::NAME_LENGTH_LIMIT + 1)),
+        b"long",
+    )?;
+    std::fs::write(
+        std::path::Path::new(src).join(OsStr::from_bytes(b"bad\xff")),
+        b"bad",
+    )?;
+    std::fs::create_dir(format!("{}dir", src))?;
+    symlink("small", format!("{}link", src))?;
+    symlink("missing", format!("{}broken", src))?;
+    let fifo = std::ffi::CString::new(format!("{}fifo", src)).unwrap();
+    assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o644) }, 0);
+
+    let plan = |symlinks, oversize, max_len| {
+        let policy = ImportPolicy { symlinks, oversize };
+        host::plan_import(src, &policy, max_len).map(|entries: Vec<ImportEntry>| {
+            let mut entries: Vec<(String, u64)> = entries
+                .into_iter()
+                .map(|entry| {
+                    assert_eq!(entry.path, format!("{}{}", src, entry.name));
+                    (entry.name, entry.len)
+                })
+                .collect();
+            entries.sort();
+            entries
+        })
+    };
+    let max_len = fs::MAX_FILE_SIZE as u64;
+    // 目录, FIFO, 断开的符号链接, 过长或者不是 UTF-8 的名称总是跳过
+    assert_eq!(
+        plan(SymlinkPolicy::Follow, OversizePolicy::Skip, max_len).unwrap(),
+        vec![("link".to_string(), 5), ("small".to_string(), 5)]
+    );
+    assert_eq!(
+        plan(SymlinkPolicy::Skip, OversizePolicy::Truncate, max_len).unwrap(),
+        vec![("big".to_string(), max_len), ("small".to_string(), 5)]
+    );
+    let err = plan(SymlinkPolicy::Skip, OversizePolicy::Error, max_len).unwrap_err();
+    assert!(err.contains("big"), "{}", err);
+    // 小于 max_len 的文件不受 --oversize 影响
+    assert!(plan(SymlinkPolicy::Skip, OversizePolicy::Error, max_len + 1).is_ok());
+
+    // 默认大小的镜像放不下 MAX_FILE_SIZE 的文件, 截断到镜像剩余的空间能容纳的大小
+    let efs = create_default_fs()?;
+    let root_inode = FileSystem::root_inode(&efs);
+    let max_len = host::max_import_len(&efs, &root_inode);
+    assert!(max_len < fs::MAX_FILE_SIZE as u64);
+    let entries = plan(SymlinkPolicy::Skip, OversizePolicy::Truncate, max_len).unwrap();
+    assert_eq!(entries[0], ("big".to_string(), max_len));
+
+    // 与 set 一样先预留, 截断后的文件可以完整导入
+    let blocks = root_inode.blocks_needed(root_inode.size() + fs::DIRENT_SIZE)
+        + fs::DiskInode::total_blocks(max_len as u32) as usize;
+    let mut reservation = FileSystem::reserve_inodes(&efs, 1).unwrap();
+    reservation.merge(FileSystem::reserve_blocks(&efs, blocks).unwrap());
+    let inode = root_inode
+        .create_reserved("big", fs::DiskInodeType::File, &mut reservation)
+        .unwrap();
+    let imported = host::import_file(
+        &mut File::open(format!("{}big", src))?,
+        &inode,
+        max_len,
+        &mut reservation,
+    )?;
+    drop(reservation);
+    assert_eq!(imported as u64, max_len);
+    assert_eq!(inode.size() as u64, max_len);
+    Ok(())
+}
+
+#[test]
+fn save_into_dir_test() -> std::io::Result<()> {
+    let _guard = lock_fs_test();
+    let efs = create_default_fs()?;
+    let root_inode = Arc::new(FileSystem::root_inode(&efs));
+
+    // 正常情况下 /.profile 与 /.manifest 不存在时创建, 存在时覆盖
+    let mut aliases = Aliases::default();
+    assert!(aliases.define("la='ls'"));
+    aliases.save(&root_inode).unwrap();
+    assert_eq!(Aliases::load(&root_inode).expand("la"), "ls");
+    assert!(Manifest::default().save(&root_inode).is_ok());
+
+    // 同名的是目录时拒绝写入, 目录中的文件不受影响
+    for name in [PROFILE, MANIFEST] {
+        root_inode.find(name).unwrap().clear();
+        let file = root_inode.find(name).unwrap();
+        file.rm_dir_entry(name, Arc::clone(&root_inode));
+        let dir = root_inode
+            .create(name, fs::DiskInodeType::Directory)
+            .unwrap();
+        dir.create("kept", fs::DiskInodeType::File)
+            .unwrap()
+            .write(0, b"kept");
+    }
+    assert!(aliases.save(&root_inode).is_err());
+    assert!(Manifest::default().save(&root_inode).is_err());
+    assert!(Aliases::load(&root_inode).iter().next().is_none());
+    assert!(Manifest::load(&root_inode).is_none());
+    for name in [PROFILE, MANIFEST] {
+        let kept = root_inode.find(name).unwrap().find("kept").unwrap();
+        assert_eq!(kept.size(), 4);
+    }
+    Ok(())
+}
+
+/// 在默认大小的镜像上使用分配策略 name: 反复写入/清空一个 1000 块的文件,
+/// 然后用一个文件占满整个数据区域, 分配出去的块都不能越过数据区域的末尾
+fn allocator_fill_test(name: &str) -> std::io::Result<()> {
+    let _guard = lock_fs_test();
+    let efs = create_default_fs()?;
+    efs.lock()
+        .set_allocator(fs::allocator_by_name(name).unwrap());
+    let root_inode = FileSystem::root_inode(&efs);
+    let file = root_inode.create("file", fs::DiskInodeType::File).unwrap();
+    let free_blocks = efs.lock().free_data_blocks();
+
+    let chunk = vec![0x5au8; 1000 * BLOCK_SIZE];
+    let mut cycle = || {
+        for _ in 0..20 {
+            assert_eq!(file.write(0, &chunk), chunk.len(), "{}", name);
+            file.clear();
+        }
+        assert_eq!(efs.lock().free_data_blocks(), free_blocks, "{}", name);
+    };
+    cycle();
+
+    // 先按 64 块, 再按 1 块追加, 直到没有空闲的数据块
+    for chunk in [vec![0xa5u8; 64 * BLOCK_SIZE], vec![0xa5u8; BLOCK_SIZE]] {
+        while file.write(file.size(), &chunk) == chunk.len() {}
+    }
+    // 最后剩下的块可能不够再分配一个数据块及其索引块
+    assert!(efs.lock().free_data_blocks() <= 2, "{}", name);
+    assert!(file.size() >= (free_blocks - 200) * BLOCK_SIZE, "{}", name);
+    let mut buf = [0u8; BLOCK_SIZE];
+    assert_eq!(file.read(file.size() - BLOCK_SIZE, &mut buf), BLOCK_SIZE);
+    assert!(buf.iter().all(|&byte| byte == 0xa5), "{}", name);
+    file.clear();
+
+    cycle();
+    Ok(())
+}
+
+#[test]
+fn first_fit_fill_test() -> std::io::Result<()> {
+    allocator_fill_test("first-fit")
+}
+
+#[test]
+fn next_fit_fill_test() -> std::io::Result<()> {
+    allocator_fill_test("next-fit")
+}
+
+#[test]
+fn best_fit_fill_test() -> std::io::Result<()> {
+    allocator_fill_test("best-fit")
+}
+
+#[test]
+fn group_aware_fill_test() -> std::io::Result<()> {
+    allocator_fill_test("group-aware")
+}
+
+#[test]
+fn best_fit_test() -> std::io::Result<()> {
+    let _guard = lock_fs_test();
+    let efs = create_default_fs()?;
+    let mut fs = efs.lock();
+    // 先顺序分配 100 个块, 再回收出长度为 3, 1, 2 的空闲区段
+    let blocks: Vec<u32> = (0..100).map(|_| fs.alloc_data().unwrap()).collect();
+    for i in [10, 11, 12, 50, 70, 71] {
+        fs.dealloc_data(blocks[i]);
+    }
+    fs.set_allocator(fs::allocator_by_name("best-fit").unwrap());
+    // 最短的区段先用完, 之后才轮到数据区域末尾的长区段
+    for i in [50, 70, 71, 10, 11, 12] {
+        assert_eq!(fs.alloc_data(), Some(blocks[i]));
+    }
+    assert_eq!(fs.alloc_data(), Some(blocks[99] + 1));
+    // 回收之后重新寻找最短的区段
+    fs.dealloc_data(blocks[30]);
+    assert_eq!(fs.alloc_data(), Some(blocks[30]));
+    assert_eq!(fs.alloc_data(), Some(blocks[99] + 2));
+    Ok(())
+}
+
+/// 直接修改磁盘上 inode 的第一个数据块 (绕过 easy-fs), 模拟篡改
+fn tamper(inode: &fs::Inode, block_device: &Arc<dyn BlockDevice>) {
+    let (block_id, block_offset) = inode.inode_info();
+    let data_block = fs::get_block_cache(block_id, Arc::clone(block_device))
+        .lock()
+        .read(block_offset, |disk_inode: &fs::DiskInode| {
+            disk_inode.get_block_id(0, block_device)
+        });
+    fs::get_block_cache(data_block as usize, Arc::clone(block_device))
+        .lock()
+        .modify(0, |data: &mut [u8; BLOCK_SIZE]| data[0] ^= 1);
+}
+
+#[test]
+fn merkle_test() -> std::io::Result<()> {
+    let _guard = lock_fs_test();
+    let efs = create_default_fs()?;
+    let block_device: Arc<dyn BlockDevice> = Arc::clone(&efs.lock().block_device);
+    let root_inode = Arc::new(FileSystem::root_inode(&efs));
+    let dir = root_inode
+        .create("dir", fs::DiskInodeType::Directory)
+        .unwrap();
+    let file = dir.create("file", fs::DiskInodeType::File).unwrap();
+    file.write(0, &[0x5au8; 3 * BLOCK_SIZE]);
+    root_inode
+        .create("other", fs::DiskInodeType::File)
+        .unwrap()
+        .write(0, b"other");
+
+    let trusted = efs.lock().merkle_on().unwrap();
+    let (stored, computed, mismatched) = efs.lock().merkle_verify().unwrap();
+    assert_eq!((stored, computed), (trusted, trusted));
+    assert!(mismatched.is_empty());
+
+    // 通过 easy-fs 的修改沿路径更新根哈希, 与重新计算的结果一致
+    file.write(BLOCK_SIZE + 7, b"hello");
+    file.write(file.size(), &[1u8; 2 * BLOCK_SIZE]);
+    dir.create("new", fs::DiskInodeType::File)
+        .unwrap()
+        .write(0, b"new");
+    dir.chname("new", "renamed");
+    let other = root_inode.find("other").unwrap();
+    other.clear();
+    other.rm_dir_entry("other", Arc::clone(&root_inode));
+    let (stored, computed, mismatched) = efs.lock().merkle_verify().unwrap();
+    assert_ne!(stored, trusted);
+    assert_eq!(stored, computed);
+    assert!(mismatched.is_empty());
+
+    // 根哈希在重新挂载之后仍然有效
+    let efs = FileSystem::open(Arc::clone(&block_device));
+    let (stored, computed, _) = efs.lock().merkle_verify().unwrap();
+    assert_eq!(stored, computed);
+    let root_inode = Arc::new(FileSystem::root_inode(&efs));
+    let dir = root_inode.find("dir").unwrap();
+    let file = dir.find("file").unwrap();
+
+    // 绕过 easy-fs 篡改文件内容, verify 发现不一致并且不会接受篡改后的内容
+    tamper(&file, &block_device);
+    for _ in 0..2 {
+        let (stored, computed, mismatched) = efs.lock().merkle_verify().unwrap();
+        assert_ne!(stored, computed);
+        assert_eq!(mismatched, vec!["/dir/file".to_string()]);
+    }
+
+    // 之后对被篡改的文件以及其他文件的正常修改也不能让篡改被接受
+    file.write(file.size(), b"append");
+    file.write(0, &[0x5au8; 16]);
+    dir.create("after", fs::DiskInodeType::File)
+        .unwrap()
+        .write(0, b"after");
+    dir.find("renamed").unwrap().write(0, b"renamed again");
+    let (stored, computed, mismatched) = efs.lock().merkle_verify().unwrap();
+    assert_ne!(stored, computed);
+    assert_eq!(mismatched, vec!["/dir/file".to_string()]);
+
+    // 整个块被覆盖之后, 文件内容已经不包含篡改的部分, 但仍然不能被接受
+    file.write(0, &[0u8; BLOCK_SIZE]);
+    let (stored, computed, _) = efs.lock().merkle_verify().unwrap();
+    assert_ne!(stored, computed);
+
+    // 删除被篡改的文件也不能让篡改被接受
+    file.clear();
+    file.rm_dir_entry("file", Arc::clone(&dir));
+    let (stored, computed, _) = efs.lock().merkle_verify().unwrap();
+    assert_ne!(stored, computed);
+
+    // 重新开启 Merkle 模式即信任当前的目录树
+    let trusted = efs.lock().merkle_on().unwrap();
+    let (stored, computed, mismatched) = efs.lock().merkle_verify().unwrap();
+    assert_eq!((stored, computed), (trusted, trusted));
+    assert!(mismatched.is_empty());
+
+    // 篡改目录项 (改名) 同样能被发现
+    tamper(&dir, &block_device);
+    let (stored, computed, mismatched) = efs.lock().merkle_verify().unwrap();
+    assert_ne!(stored, computed);
+    assert_eq!(mismatched, vec!["/dir/".to_string()]);
+
+    efs.lock().merkle_off();
+    assert!(efs.lock().merkle_verify().is_none());
+    Ok(())
+}